@@ -0,0 +1,49 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::devices::{AdbDevice, DeviceEvent};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // keyed randomly per run, so tokens can't be matched back to serials across runs
+    static ref KEYS: RandomState = RandomState::new();
+}
+
+/// Makes textual output replace serials with tokens from `anonymize_serial`
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// A short token standing in for `serial`, e.g. `device-a1b2`. The same serial always maps to the
+/// same token within a run.
+pub fn anonymize_serial(serial: &str) -> String {
+    format!("device-{:04x}", KEYS.hash_one(serial) & 0xffff)
+}
+
+/// The serial to print, anonymized if that was asked for
+pub fn display_serial(serial: &str) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        anonymize_serial(serial)
+    } else {
+        serial.to_string()
+    }
+}
+
+/// Replaces the serial of a device that is about to be printed
+pub fn display_device(mut device: AdbDevice) -> AdbDevice {
+    device.connection_name = display_serial(&device.connection_name);
+    device
+}
+
+/// An event as it should be printed, anonymizing its serial if enabled
+pub fn display_event(event: DeviceEvent) -> DeviceEvent {
+    match event {
+        DeviceEvent::Connected(device) => DeviceEvent::Connected(display_device(device)),
+        DeviceEvent::Disconnected(serial) => DeviceEvent::Disconnected(display_serial(&serial)),
+        DeviceEvent::StateChanged(device) => DeviceEvent::StateChanged(display_device(device)),
+    }
+}
@@ -1,35 +1,210 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
 use quick_error::quick_error;
 use regex::Regex;
-use tokio::pin;
+use tokio::{
+    pin,
+    time::{Interval, MissedTickBehavior},
+};
 use tokio_stream::StreamExt;
+use tui::style::{Color, Style};
 
-use crate::commands::adb;
+use crate::{
+    commands::adb::{self, AdbTransport},
+    devices::online_devices,
+};
 
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
-        Io(err: std::io::Error) {
-            from()
+        Io(err: std::io::Error) {}
+        /// Worth trying again once the device is back
+        DeviceOffline {
+            display("device offline")
         }
         NotFound
+        Timeout {
+            display("timeout")
+        }
+        /// The device is connected but in a state like recovery that can't report its battery
+        NotBooted(state: String) {
+            display("{}", state)
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        if adb::is_device_offline(&err) {
+            Error::DeviceOffline
+        } else {
+            Error::Io(err)
+        }
+    }
+}
+
+pub const DEFAULT_BATTERY_INTERVAL: Duration = Duration::from_secs(10);
+
+// how long `battery_all` waits on any one device
+const BATTERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+static BATTERY_INTERVAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_BATTERY_INTERVAL.as_millis() as u64);
+
+/// Sets how often battery levels are polled, in the selector and the logcat status bar alike
+pub fn set_battery_interval(interval: Duration) {
+    BATTERY_INTERVAL_MS.store(interval.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Ticks whenever battery levels are due to be polled again, starting right away
+pub fn battery_ticks() -> Interval {
+    let period = Duration::from_millis(BATTERY_INTERVAL_MS.load(Ordering::Relaxed));
+    let mut ticks = tokio::time::interval(period);
+    // a slow device shouldn't cause a burst of catch-up polls
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticks
+}
+
+/// What `dumpsys battery` says about a device's battery
+#[derive(Debug, Clone)]
+pub struct BatteryInfo {
+    /// Percentage charged
+    pub level: i32,
+    /// Plugged in to a charger of any kind
+    pub charging: bool,
+    /// One of the `BatteryManager.BATTERY_HEALTH_*` constants, if reported
+    pub health: Option<i32>,
+}
+
+impl BatteryInfo {
+    /// The health if it's anything but good, e.g. `overheat`
+    pub fn problem(&self) -> Option<&'static str> {
+        match self.health? {
+            3 => Some("overheat"),
+            4 => Some("dead"),
+            5 => Some("over voltage"),
+            6 => Some("failure"),
+            7 => Some("cold"),
+            // unknown or good
+            _ => None,
+        }
     }
 }
 
-pub async fn battery() -> Result<i32, Error> {
+/// Battery percentage of a device, from the `level` and `scale` lines of `dumpsys battery`
+pub async fn battery(transport: &dyn AdbTransport, serial: Option<&str>) -> Result<i32, Error> {
+    Ok(battery_info(transport, serial).await?.level)
+}
+
+/// Battery percentage of every connected device by serial, queried all at once. A device that
+/// doesn't answer in time fails with `Error::Timeout` rather than holding up the rest.
+pub async fn battery_all() -> Vec<(String, Result<i32, Error>)> {
+    let mut devices = online_devices().await;
+    devices.sort_by(|a, b| a.connection_name.cmp(&b.connection_name));
+
+    let levels = futures::future::join_all(devices.iter().map(|device| async {
+        let state = &device.properties.connection_state;
+        if state != "device" {
+            return Err(Error::NotBooted(state.clone()));
+        }
+        tokio::time::timeout(
+            BATTERY_TIMEOUT,
+            battery(adb::transport(), Some(&device.connection_name)),
+        )
+        .await
+        .unwrap_or(Err(Error::Timeout))
+    }))
+    .await;
+
+    devices
+        .into_iter()
+        .map(|device| device.connection_name)
+        .zip(levels)
+        .collect()
+}
+
+/// The battery's level, charging state, and health
+pub async fn battery_info(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+) -> Result<BatteryInfo, Error> {
     lazy_static::lazy_static! {
         static ref RE: Regex = Regex::new(r"(?x)
-        ^\s\slevel:\s(?P<level>[[:xdigit:]]+)").unwrap();
+        ^\s\s(?P<key>level|scale|health|(?:AC|USB|Wireless|Dock)\spowered):\s(?P<value>[[:alnum:]]+)").unwrap();
     }
 
-    let stream = adb::shell("dumpsys battery");
+    let stream = adb::shell(transport, serial, "dumpsys battery");
     pin!(stream);
 
+    let mut level = None;
+    let mut scale: Option<i32> = None;
+    let mut health = None;
+    let mut charging = false;
     while let Some(line) = stream.next().await {
         let line = line?;
         if let Some(captures) = RE.captures(&line) {
-            return Ok(i32::from_str_radix(&captures["level"], 10).unwrap());
+            let value = &captures["value"];
+            match &captures["key"] {
+                "level" => level = value.parse().ok(),
+                "scale" => scale = value.parse().ok(),
+                "health" => health = value.parse().ok(),
+                _ => charging |= value == "true",
+            }
         }
     }
 
-    Err(Error::NotFound)
+    let level: i32 = level.ok_or(Error::NotFound)?;
+
+    // `level` is only a percentage when the scale is 100, which most but not all devices use
+    let level = match scale {
+        Some(scale) if scale > 0 && scale != 100 => (level * 100 + scale / 2) / scale,
+        _ => level,
+    };
+
+    Ok(BatteryInfo {
+        level,
+        charging,
+        health,
+    })
+}
+
+/// Battery info for a status line, like `85%⚡`, colored when it needs attention
+pub fn format_battery(info: &BatteryInfo) -> (String, Style) {
+    let mut text = format!("{}%", info.level);
+    if info.charging {
+        text.push('⚡');
+    }
+    if let Some(problem) = info.problem() {
+        text.push_str(&format!(" ⚠ {problem}"));
+    }
+
+    let style = if info.charging {
+        Style::default().fg(Color::LightGreen)
+    } else if info.level < 20 {
+        Style::default().fg(Color::LightRed)
+    } else {
+        Style::default()
+    };
+    (text, style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn level_is_scaled_to_a_percentage() {
+        let adb = adb::MockAdb::new().with_output(
+            "-s emulator-5554 shell dumpsys battery",
+            "Current Battery Service state:\n  AC powered: false\n  USB powered: true\n  \
+             level: 200\n  scale: 255\n  health: 2\n",
+        );
+
+        let info = battery_info(&adb, Some("emulator-5554")).await.unwrap();
+        assert_eq!(info.level, 78);
+        assert!(info.charging);
+        assert_eq!(info.problem(), None);
+    }
 }
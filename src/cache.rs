@@ -1,4 +1,11 @@
-use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use fd_lock::RwLock;
 use home::home_dir;
@@ -9,9 +16,12 @@ use tokio::{
     io::{AsyncWriteExt, BufWriter},
 };
 
-use crate::devices::AdbDeviceProperties;
+use crate::{
+    devices::AdbDeviceProperties,
+    widgets::log::{ColorBy, TimeFormat},
+};
 
-fn xadb_dir() -> PathBuf {
+pub fn xadb_dir() -> PathBuf {
     if let Ok(xadb_dir) = std::env::var("XADB_DIR") {
         PathBuf::from_str(&xadb_dir).unwrap()
     } else {
@@ -21,10 +31,49 @@ fn xadb_dir() -> PathBuf {
     }
 }
 
+const LOCK_MIN_BACKOFF: Duration = Duration::from_millis(10);
+const LOCK_MAX_BACKOFF: Duration = Duration::from_millis(160);
+
 fn cache_location() -> PathBuf {
     xadb_dir().join("cache.json")
 }
 
+/// What identifies a device in the cache
+#[derive(clap::ValueEnum, Deserialize, Copy, Clone, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheKey {
+    /// The name adb lists the device under
+    #[default]
+    Serial,
+    /// The device's own serial number, so a network device reconnecting on a new port replaces its
+    /// old entry instead of adding another
+    Serialno,
+}
+
+static KEY_BY_SERIALNO: AtomicBool = AtomicBool::new(false);
+
+pub fn set_cache_key(key: CacheKey) {
+    KEY_BY_SERIALNO.store(matches!(key, CacheKey::Serialno), Ordering::Relaxed);
+}
+
+/// Whether devices' serial numbers have to be looked up to key the cache
+pub fn keyed_by_serialno() -> bool {
+    KEY_BY_SERIALNO.load(Ordering::Relaxed)
+}
+
+/// Identifies the physical device behind `serial` according to the `CacheKey` in use. Devices
+/// whose serial number hasn't been looked up yet fall back to `serial`.
+pub fn device_key(serial: &str, properties: &AdbDeviceProperties) -> String {
+    let serialno = properties
+        .live
+        .as_ref()
+        .and_then(|live| live.serialno.as_ref());
+    match serialno {
+        Some(serialno) if keyed_by_serialno() => serialno.clone(),
+        _ => serial.to_string(),
+    }
+}
+
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
@@ -41,6 +90,37 @@ quick_error! {
 pub struct Cache {
     pub version: String,
     pub devices: HashMap<String, AdbDeviceProperties>,
+    /// Serial most recently picked in the selector, used when `ANDROID_SERIAL` isn't set
+    #[serde(default)]
+    pub last_selected: Option<String>,
+    /// How each device's log was last viewed, by serial
+    #[serde(default)]
+    pub per_device_ui: HashMap<String, UiPrefs>,
+    /// When each device was last seen connected, in seconds since the Unix epoch, by serial
+    #[serde(default)]
+    pub last_seen: HashMap<String, i64>,
+    /// Set by `--no-cache`, in which case nothing is ever written back to disk
+    #[serde(skip)]
+    in_memory: bool,
+}
+
+/// View settings for one device, restored the next time its log is opened. Anything set by a flag,
+/// the environment or config.toml wins over these, and `xadb logcat --reset-prefs` ignores them.
+/// The selector lists every device at once, so it has nothing to keep per device.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiPrefs {
+    pub compact: bool,
+    pub multiline: bool,
+    pub show_pid: bool,
+    pub show_tid: bool,
+    pub line_numbers: bool,
+    pub color_by: Option<ColorBy>,
+    pub time_format: Option<TimeFormat>,
+    /// Log ids shown, or every buffer if empty
+    pub buffers: Vec<u32>,
+    /// Whether the view was paused rather than following new messages
+    pub paused: bool,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -51,17 +131,34 @@ impl Cache {
         Ok(())
     }
 
+    fn empty() -> Cache {
+        Cache {
+            version: clap::crate_version!().to_string(),
+            devices: Default::default(),
+            last_selected: None,
+            per_device_ui: Default::default(),
+            last_seen: Default::default(),
+            in_memory: false,
+        }
+    }
+
+    /// Loads the cache from disk, or starts an empty one that is never persisted if `in_memory`
+    pub async fn load(in_memory: bool) -> Result<Cache> {
+        if in_memory {
+            return Ok(Cache {
+                in_memory: true,
+                ..Cache::empty()
+            });
+        }
+
+        Cache::load_from_disk().await
+    }
+
     pub async fn load_from_disk() -> Result<Cache> {
         match tokio::fs::read_to_string(cache_location()).await {
-            Ok(contents) if contents.is_empty() => Ok(Cache {
-                version: clap::crate_version!().to_string(),
-                devices: Default::default(),
-            }),
+            Ok(contents) if contents.is_empty() => Ok(Cache::empty()),
             Ok(contents) => Ok(serde_json::from_str(&contents)?),
-            Err(_) => Ok(Cache {
-                version: clap::crate_version!().to_string(),
-                devices: Default::default(),
-            }),
+            Err(_) => Ok(Cache::empty()),
         }
     }
 
@@ -70,20 +167,84 @@ impl Cache {
             .entry(serial.to_owned())
             .and_modify(|e| {
                 if let Some(live) = &properties.live {
-                    e.live = Some(live.clone());
+                    let mut live = live.clone();
+                    if let Some(previous) = &e.live {
+                        live.carry_over_lookups(previous);
+                    }
+                    e.live = Some(live);
                 }
 
                 e.connection_state = properties.connection_state.clone();
                 e.devpath = properties.devpath.clone();
             })
             .or_insert_with(|| properties.clone());
+        self.last_seen
+            .insert(serial.to_owned(), chrono::Utc::now().timestamp());
+
+        self.replace_duplicates(serial, properties);
+    }
+
+    // entries under other serials for the same device are from earlier connections, so they're
+    // folded into this one
+    fn replace_duplicates(&mut self, serial: &str, properties: &AdbDeviceProperties) {
+        let key = device_key(serial, properties);
+        let duplicates: Vec<String> = self
+            .devices
+            .iter()
+            .filter(|(other, other_properties)| {
+                *other != serial && device_key(other, other_properties) == key
+            })
+            .map(|(other, _)| other.clone())
+            .collect();
+
+        for duplicate in duplicates {
+            tracing::debug!("{duplicate} is the same device as {serial}, replacing it");
+            if let Some(true) = self.devices.remove(&duplicate).map(|d| d.favorite) {
+                if let Some(properties) = self.devices.get_mut(serial) {
+                    properties.favorite = true;
+                }
+            }
+            self.last_seen.remove(&duplicate);
+            if let Some(prefs) = self.per_device_ui.remove(&duplicate) {
+                self.per_device_ui
+                    .entry(serial.to_string())
+                    .or_insert(prefs);
+            }
+            if self.last_selected.as_deref() == Some(duplicate.as_str()) {
+                self.last_selected = Some(serial.to_string());
+            }
+        }
     }
 
-    pub fn remove_device(&mut self, serial: &str) {
-        self.devices.remove(serial);
+    /// Pins a device to the top of the selector, or unpins it if it already was. Returns whether
+    /// it's now a favorite.
+    pub fn toggle_favorite(&mut self, serial: &str) -> bool {
+        match self.devices.get_mut(serial) {
+            Some(properties) => {
+                properties.favorite = !properties.favorite;
+                properties.favorite
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets everything about a device, returning whether anything was known about it
+    pub fn remove_device(&mut self, serial: &str) -> bool {
+        let mut removed = self.devices.remove(serial).is_some();
+        removed |= self.per_device_ui.remove(serial).is_some();
+        removed |= self.last_seen.remove(serial).is_some();
+        if self.last_selected.as_deref() == Some(serial) {
+            self.last_selected = None;
+            removed = true;
+        }
+        removed
     }
 
     pub async fn persist(&self) -> Result<()> {
+        if self.in_memory {
+            return Ok(());
+        }
+
         tokio::fs::create_dir_all(xadb_dir()).await?;
 
         let mut cache_file = RwLock::new(
@@ -95,7 +256,23 @@ impl Cache {
                 .await?,
         );
 
-        let mut cache_file = cache_file.try_write()?;
+        // another xadb may be refreshing the cache at the same moment, so wait briefly for it
+        let mut backoff = LOCK_MIN_BACKOFF;
+        let mut cache_file = loop {
+            match cache_file.try_write() {
+                Ok(cache_file) => break cache_file,
+                Err(err) if err.kind() != io::ErrorKind::WouldBlock => return Err(err.into()),
+                Err(_) if backoff > LOCK_MAX_BACKOFF => {
+                    // persisting is best-effort, the other process's write is just as fresh
+                    tracing::debug!("cache is locked by another process, skipping write");
+                    return Ok(());
+                }
+                Err(_) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        };
         cache_file.set_len(0).await?;
 
         let mut writer = BufWriter::new(&mut *cache_file);
@@ -1,8 +1,112 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
 
+use crate::{
+    cache::CacheKey,
+    commands::adb::{parse_filterspec, parse_log_id, parse_socket, LogFormat, LogLevel},
+    device_select::DeviceColumn,
+    theme::ColorChoice,
+    widgets::log::{ColorBy, TimeFormat},
+};
+
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
+    #[clap(
+        short = 'v',
+        long,
+        global = true,
+        help = "Log debug output (to $XADB_DIR/xadb.log for TUI commands)"
+    )]
+    pub debug: bool,
+    #[clap(
+        long,
+        global = true,
+        env = "XADB_NO_DEFAULT",
+        value_parser = clap::builder::FalseyValueParser::new(),
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Don't fall back to the last selected device when ANDROID_SERIAL isn't set"
+    )]
+    pub no_default: Option<bool>,
+    #[clap(
+        long,
+        global = true,
+        env = "XADB_NO_CACHE",
+        value_parser = clap::builder::FalseyValueParser::new(),
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Don't read or write the device cache in $XADB_DIR"
+    )]
+    pub no_cache: Option<bool>,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        env = "XADB_CACHE_KEY",
+        help = "What identifies a device in the cache [default: serial]"
+    )]
+    pub cache_key: Option<CacheKey>,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        help = "Device properties to show in the selector, in order [default: serial,state,product,version]"
+    )]
+    pub columns: Vec<DeviceColumn>,
+    #[clap(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        env = "XADB_BATTERY_INTERVAL",
+        help = "How often to poll battery levels in the selector and logcat [default: 10]"
+    )]
+    pub battery_interval: Option<u64>,
+    #[clap(
+        long,
+        global = true,
+        env = "XADB_NO_ADB",
+        value_parser = clap::builder::FalseyValueParser::new(),
+        conflicts_with = "no_fastboot",
+        help = "Only look for devices in fastboot mode, without asking adb"
+    )]
+    pub no_adb: bool,
+    #[clap(
+        long,
+        global = true,
+        env = "XADB_NO_FASTBOOT",
+        value_parser = clap::builder::FalseyValueParser::new(),
+        help = "Don't look for devices in fastboot mode"
+    )]
+    pub no_fastboot: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Print the adb and fastboot commands that would be run instead of running them"
+    )]
+    pub dry_run: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Replace serials in printed device lists and commands with stable tokens"
+    )]
+    pub anonymize: bool,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        value_name = "WHEN",
+        default_value_t,
+        help = "When to use color; auto uses it only on a terminal and when NO_COLOR isn't set"
+    )]
+    pub color: ColorChoice,
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -10,21 +114,373 @@ pub struct Args {
 #[derive(Subcommand)]
 pub enum Command {
     #[clap(about = "Interactive list of adb devices")]
-    List,
+    List {
+        #[clap(
+            long,
+            help = "Print the only device that can be used without showing the list, if there is just one"
+        )]
+        once: bool,
+        #[clap(
+            long,
+            help = "Show the list in the lines below the cursor instead of taking over the screen"
+        )]
+        inline: bool,
+    },
     #[clap(about = "Clear xadb cache")]
-    ClearCache,
+    ClearCache {
+        #[clap(long, help = "Only forget this device, keeping the rest of the cache")]
+        serial: Option<String>,
+    },
     #[clap(about = "Get product for currently selected adb device")]
     CurrentProduct,
+    #[clap(
+        about = "Print a short status of the selected device for a shell prompt",
+        long_about = "Print a short status of the selected device for a shell prompt. The product \
+                      is followed by * if it's the only connected device, +N if N other devices \
+                      are connected, or ! if it isn't connected. The cache is read instead of \
+                      running adb, so the product is the one last seen."
+    )]
+    Prompt,
     #[clap(about = "Print shell integration function")]
     InitShell { shell: String },
     #[clap(about = "Interactively select adb device to use in current shell")]
     Select,
+    #[clap(
+        about = "Print the serial of the device commands would target",
+        long_about = "Print the serial of the device commands would target: the one \
+                      ANDROID_SERIAL refers to, the last selected device, or the only connected \
+                      device. Fails if that's ambiguous."
+    )]
+    Which,
+    #[clap(
+        about = "Remember a device as the default without the interactive list",
+        long_about = "Remember a device as the default without the interactive list, as if it \
+                      had been selected. It's used when ANDROID_SERIAL isn't set."
+    )]
+    Use {
+        #[clap(help = "A serial, transport id, or product, model, or device name")]
+        device: String,
+    },
+    #[clap(
+        about = "Print connected and cached devices",
+        long_about = "Print connected and cached devices, one per line: serial, connection state, \
+                      product, model, device, transport id, and when the device was last seen \
+                      connected. Devices only known from the cache are listed as offline."
+    )]
+    Devices {
+        #[clap(
+            long,
+            help = "Print CSV with a header row instead of tab separated columns"
+        )]
+        csv: bool,
+    },
+    #[clap(
+        about = "Pair with a device for wireless debugging",
+        long_about = "Pair with a device for wireless debugging, as with `adb pair`. Use the \
+                      address and code from \"Pair device with pairing code\" under Wireless \
+                      debugging in the device's developer options. Pairing is only needed once per \
+                      computer; `adb connect` then connects to the address Wireless debugging \
+                      shows, which is on a different port."
+    )]
+    Pair {
+        #[clap(help = "The pairing address, like 192.168.1.20:37215")]
+        addr: String,
+        #[clap(help = "The 6 digit pairing code, asked for if not given")]
+        code: Option<String>,
+        #[clap(
+            long,
+            value_name = "HOST:PORT",
+            help = "Connect to the device at this address once paired"
+        )]
+        connect: Option<String>,
+    },
+    #[clap(
+        about = "Forward a port on this computer to a device",
+        long_about = "Forward a port on this computer to a device, as with `adb forward`. Sockets \
+                      are adb's, like tcp:8080 or localabstract:chrome_devtools_remote; a bare \
+                      port number means tcp."
+    )]
+    Forward {
+        #[clap(
+            value_parser = parse_socket,
+            required_unless_present = "list",
+            help = "The socket to listen on here, e.g. 8080"
+        )]
+        local: Option<String>,
+        #[clap(
+            value_parser = parse_socket,
+            required_unless_present = "list",
+            help = "The socket to connect to on the device"
+        )]
+        remote: Option<String>,
+        #[clap(
+            long,
+            conflicts_with_all = ["local", "remote"],
+            help = "List the device's forwarded ports instead"
+        )]
+        list: bool,
+    },
+    #[clap(
+        about = "Forward a port on a device back to this computer",
+        long_about = "Forward a port on a device back to this computer, as with `adb reverse`, so \
+                      apps can reach a server running here. Sockets are as for `xadb forward`."
+    )]
+    Reverse {
+        #[clap(
+            value_parser = parse_socket,
+            required_unless_present = "list",
+            help = "The socket to listen on on the device, e.g. 8081"
+        )]
+        remote: Option<String>,
+        #[clap(
+            value_parser = parse_socket,
+            required_unless_present = "list",
+            help = "The socket to connect to here"
+        )]
+        local: Option<String>,
+        #[clap(
+            long,
+            conflicts_with_all = ["local", "remote"],
+            help = "List the device's reversed ports instead"
+        )]
+        list: bool,
+    },
     #[clap(about = "Get battery level for adb device")]
-    Battery,
+    Battery {
+        #[clap(
+            long,
+            help = "Print a `serial: level%` line for every connected device instead"
+        )]
+        all: bool,
+    },
+    #[clap(
+        about = "Install an OTA package on a device in sideload mode",
+        long_about = "Install an OTA package on a device in sideload mode, as with `adb sideload`. \
+                      Pick \"Apply update from ADB\" in the device's recovery menu first."
+    )]
+    Sideload {
+        #[clap(help = "The OTA package to install")]
+        package: PathBuf,
+    },
+    #[clap(about = "Browse the output of dumpsys for a service")]
+    Dumpsys {
+        #[clap(help = "The service to dump, e.g. battery or activity")]
+        service: String,
+        #[clap(help = "Arguments passed on to the service")]
+        args: Vec<String>,
+        #[clap(long, help = "Print the output instead of showing it in a pager")]
+        raw: bool,
+    },
     #[clap(about = "Enhanced logcat")]
-    Logcat,
+    Logcat(LogcatArgs),
+    #[clap(
+        about = "Print a line whenever a device connects, disconnects, or changes state",
+        long_about = "Print a line whenever a device connects, disconnects, or changes state, \
+                      until interrupted. Lines look like `connected <serial> <state> <product>`, \
+                      `disconnected <serial>`, or `state <serial> <state>`. Devices already \
+                      connected are reported first. The on-connect command in config.toml is run \
+                      for each device that becomes usable over adb."
+    )]
+    Watch {
+        #[clap(long, value_name = "N", help = "Exit after printing N events")]
+        max_events: Option<usize>,
+    },
     #[clap(about = "debug")]
-    TrackDevices,
+    TrackDevices {
+        #[clap(long, value_name = "N", help = "Exit after printing N device lists")]
+        max_events: Option<usize>,
+    },
     #[clap(about = "debug")]
-    AllDevices,
+    AllDevices {
+        #[clap(long, value_name = "N", help = "Exit after printing N device lists")]
+        max_events: Option<usize>,
+    },
+}
+
+#[derive(clap::Args, Clone)]
+pub struct LogcatArgs {
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Only read the text log buffers (main, system, crash), skipping binary buffers"
+    )]
+    pub text_only: Option<bool>,
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        env = "XADB_MIN_LEVEL",
+        help = "Have the device only send messages at or above LEVEL (V, D, I, W, E, F)"
+    )]
+    pub min_level: Option<LogLevel>,
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        help = "Messages at or above LEVEL are the ones ] and [ jump between [default: W]"
+    )]
+    pub jump_level: Option<LogLevel>,
+    #[clap(
+        long,
+        value_enum,
+        env = "XADB_COLOR_BY",
+        help = "How to color log rows [default: level]"
+    )]
+    pub color_by: Option<ColorBy>,
+    #[clap(
+        long,
+        value_enum,
+        env = "XADB_TIME_FORMAT",
+        help = "Show when messages were logged, in UTC or local time, or how long ago (cycle with r) \
+                [default: absolute]"
+    )]
+    pub time_format: Option<TimeFormat>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Start in the compact layout (toggle with c)"
+    )]
+    pub compact: Option<bool>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Show each line of multi-line messages on its own row (toggle with m)"
+    )]
+    pub multiline: Option<bool>,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = LogFormat::Threadtime,
+        help = "How messages are written by --print and the w key, as with adb logcat -v"
+    )]
+    pub format: LogFormat,
+    #[clap(
+        long,
+        help = "Print messages to stdout in --format instead of showing the log view"
+    )]
+    pub print: bool,
+    #[clap(
+        long = "buffer",
+        value_name = "BUFFER",
+        value_parser = parse_log_id,
+        help = "Only show messages from this buffer, e.g. security (repeatable)"
+    )]
+    pub buffers: Vec<u32>,
+    #[clap(
+        long,
+        num_args = 2,
+        value_names = ["BUFFER", "BUFFER"],
+        value_parser = parse_log_id,
+        conflicts_with_all = ["text_only", "buffers"],
+        help = "Show two buffers side by side, e.g. --split main crash. Tab switches between them \
+                and S scrolls them together."
+    )]
+    pub split: Vec<u32>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Mark each point where the log switches from one buffer to another, like adb \
+                logcat -D"
+    )]
+    pub dividers: Option<bool>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Leave ANSI escape sequences and other control characters in messages instead of \
+                removing them"
+    )]
+    pub keep_ansi: Option<bool>,
+    #[clap(
+        long,
+        help = "Pick the device in the selector even if ANDROID_SERIAL is set"
+    )]
+    pub pick: bool,
+    #[clap(
+        long,
+        help = "Start from the default view instead of the one saved for the device, replacing it \
+                on quitting"
+    )]
+    pub reset_prefs: bool,
+    #[clap(
+        long = "pid",
+        value_name = "PID",
+        help = "Have the device only send messages from this process (repeatable)"
+    )]
+    pub pids: Vec<u32>,
+    #[clap(
+        long,
+        value_name = "SPEC",
+        value_parser = parse_filterspec,
+        help = "Pass adb's TAG:LEVEL filterspecs to the device as is, e.g. \"ActivityManager:I *:S\""
+    )]
+    pub filterspec: Option<String>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Show the id of the process that logged each message (toggle with p)"
+    )]
+    pub show_pid: Option<bool>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Show the id of the thread that logged each message (toggle with P)"
+    )]
+    pub show_tid: Option<bool>,
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+        help = "Number each message, counting from the first one kept (toggle with n)"
+    )]
+    pub line_numbers: Option<bool>,
+    #[clap(
+        long,
+        value_name = "URL",
+        env = "XADB_EDITOR_URL",
+        help = "Make File.kt:123 references clickable in terminals that support it, opening URL \
+                with {file} and {line} filled in, e.g. idea://open?file={file}&line={line}"
+    )]
+    pub editor_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "BYTES",
+        help = "How much of adb's output to read at a time; larger keeps up with chattier \
+                devices [default: 65536]"
+    )]
+    pub read_buffer_size: Option<usize>,
+}
+
+impl LogcatArgs {
+    /// The options `xadb logcat` has without any flags, for logs opened some other way. These can
+    /// still be set through the environment.
+    pub fn from_env() -> LogcatArgs {
+        #[derive(Parser)]
+        struct Logcat {
+            #[clap(flatten)]
+            args: LogcatArgs,
+        }
+
+        Logcat::parse_from(["xadb"]).args
+    }
 }
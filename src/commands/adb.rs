@@ -1,68 +1,951 @@
-use std::process::Stdio;
+use std::{
+    path::Path,
+    pin::Pin,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
 
-use async_stream::try_stream;
+use async_stream::{stream, try_stream};
+use futures::future::BoxFuture;
+use quick_error::quick_error;
+use regex::Regex;
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, ReadBuf},
+    net::TcpStream,
+    process::{Child, ChildStderr, ChildStdout, Command},
 };
 use tokio_stream::{Stream, StreamExt};
 use tokio_util::codec::FramedRead;
 
-use crate::devices::AdbDevice;
+use crate::{anonymize::display_serial, devices::AdbDevice};
 
 mod logcat;
 
+quick_error! {
+    /// Failures adb reports on stderr that callers may want to handle, like by retrying once the
+    /// device is back
+    #[derive(Debug)]
+    pub enum Error {
+        DeviceOffline {
+            display("device offline")
+        }
+        DeviceNotFound(serial: String) {
+            display("device '{}' not found", serial)
+        }
+    }
+}
+
+impl Error {
+    /// Recognizes the error adb printed to stderr before exiting, if it's a known one
+    fn from_stderr(stderr: &str) -> Option<Error> {
+        lazy_static::lazy_static! {
+            static ref NOT_FOUND: Regex = Regex::new(r"device '([^']*)' not found").unwrap();
+        }
+
+        if stderr.contains("device offline") {
+            return Some(Error::DeviceOffline);
+        }
+        NOT_FOUND
+            .captures(stderr)
+            .map(|captures| Error::DeviceNotFound(captures[1].to_string()))
+    }
+}
+
+/// Whether `err` is adb saying the device went offline, which is worth retrying rather than
+/// treating as a failure
+pub fn is_device_offline(err: &tokio::io::Error) -> bool {
+    matches!(
+        err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+        Some(Error::DeviceOffline)
+    )
+}
+
 fn get_adb() -> Command {
     tokio::process::Command::new("adb")
 }
 
-pub fn shell(command: &str) -> impl Stream<Item = tokio::io::Result<String>> {
-    let adb = get_adb()
-        .arg("shell")
-        .args(shell_words::split(command).unwrap().as_slice())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
+pub(crate) fn spawn(command: &mut Command) -> tokio::io::Result<Child> {
+    tracing::debug!("spawning {:?}", command.as_std());
+    command.spawn()
+}
+
+// processes whose output is being read, kept so `kill_children` can stop any still running
+static CHILDREN: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+
+/// The stdout of a process, which fails at the end with the error it printed to stderr if that's
+/// one `Error` knows. Dropping it kills the process, since nothing will read what it prints.
+pub(crate) struct ChildOutput {
+    // to find the process in `CHILDREN`
+    pid: Option<u32>,
+    stdout: ChildStdout,
+    // taken once it's been read to the end
+    stderr: Option<ChildStderr>,
+    stderr_contents: Vec<u8>,
+}
+
+impl AsyncRead for ChildOutput {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.stdout).poll_read(cx, buf))?;
+        if buf.filled().len() > filled || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        // stdout has ended, so whatever went wrong has been written to stderr by now
+        if let Some(stderr) = &mut this.stderr {
+            loop {
+                let mut chunk = [0; 1024];
+                let mut chunk = ReadBuf::new(&mut chunk);
+                ready!(Pin::new(&mut *stderr).poll_read(cx, &mut chunk))?;
+                if chunk.filled().is_empty() {
+                    break;
+                }
+                this.stderr_contents.extend_from_slice(chunk.filled());
+            }
+            this.stderr = None;
+
+            let stderr = String::from_utf8_lossy(&this.stderr_contents);
+            if let Some(err) = Error::from_stderr(&stderr) {
+                return Poll::Ready(Err(tokio::io::Error::other(err)));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Spawns `command` with its stdout piped, returning the stdout. The process is tracked until it
+/// exits so `kill_children` can stop it.
+pub(crate) fn spawn_output(command: &mut Command) -> tokio::io::Result<ChildOutput> {
+    let mut child = spawn(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    let stdout = ChildOutput {
+        pid: child.id(),
+        stdout: child.stdout.take().unwrap(),
+        stderr: child.stderr.take(),
+        stderr_contents: Vec::new(),
+    };
+
+    let mut children = CHILDREN.lock().unwrap();
+    // forget the ones that have finished so the list doesn't grow with every command
+    children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+    children.push(child);
+    Ok(stdout)
+}
+
+impl Drop for ChildOutput {
+    fn drop(&mut self) {
+        let Some(pid) = self.pid else {
+            return;
+        };
+        let mut children = CHILDREN.lock().unwrap();
+        if let Some(child) = children.iter_mut().find(|child| child.id() == Some(pid)) {
+            if let Err(err) = child.start_kill() {
+                tracing::debug!("failed to kill {pid}: {err:?}");
+            }
+        }
+    }
+}
+
+/// Kills every adb and fastboot process still running, like a `track-devices` that would otherwise
+/// keep running until it next had something to write
+pub fn kill_children() {
+    for mut child in CHILDREN.lock().unwrap().drain(..) {
+        if let Err(err) = child.start_kill() {
+            tracing::debug!("failed to kill {:?}: {err:?}", child.id());
+        }
+    }
+}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Makes adb and fastboot invocations print their command line instead of running
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Prints a command line in a form that can be pasted back into a shell
+pub(crate) fn print_command(program: &str, args: &[String]) {
+    let mut args = args.to_vec();
+    if let Some(serial) = args.iter().position(|arg| arg == "-s").map(|i| i + 1) {
+        if let Some(serial) = args.get_mut(serial) {
+            *serial = display_serial(serial);
+        }
+    }
 
-    let stdout = BufReader::new(adb.stdout.unwrap());
-    let mut lines = stdout.lines();
+    println!(
+        "{}",
+        shell_words::join(std::iter::once(program).chain(args.iter().map(String::as_str)))
+    );
+}
+
+/// Everything adb writes to stdout for one invocation
+pub type AdbOutput = Pin<Box<dyn AsyncRead + Send>>;
+
+/// How adb gets run. `RealAdb` spawns the adb binary, while `MockAdb` replays canned output so the
+/// parsing and decoding built on top of it can be exercised without a device.
+pub trait AdbTransport: Sync {
+    /// Runs `adb <args>`, returning its stdout
+    fn run(&self, args: &[String]) -> tokio::io::Result<AdbOutput>;
+
+    /// Runs a short `adb <args>` to completion, returning its trimmed stdout. Fails with what adb
+    /// printed to stderr if it exits unsuccessfully.
+    fn run_checked(&self, args: &[String]) -> BoxFuture<'static, tokio::io::Result<String>> {
+        let output = self.run(args);
+        Box::pin(async move {
+            let mut stdout = String::new();
+            output?.read_to_string(&mut stdout).await?;
+            Ok(stdout.trim().to_string())
+        })
+    }
+
+    /// Asks an already running adb server for a host service like `host:devices` over its socket,
+    /// returning the response. This is much quicker than spawning adb, but fails rather than
+    /// starting the server.
+    fn host_service(&self, service: &str) -> BoxFuture<'static, tokio::io::Result<Vec<u8>>> {
+        let err = tokio::io::Error::new(
+            tokio::io::ErrorKind::Unsupported,
+            format!("can't ask for {service} without an adb server"),
+        );
+        Box::pin(async move { Err(err) })
+    }
+}
+
+pub struct RealAdb;
+
+impl AdbTransport for RealAdb {
+    fn run(&self, args: &[String]) -> tokio::io::Result<AdbOutput> {
+        Ok(Box::pin(spawn_output(get_adb().args(args))?))
+    }
+
+    fn run_checked(&self, args: &[String]) -> BoxFuture<'static, tokio::io::Result<String>> {
+        let args = args.to_vec();
+        Box::pin(async move {
+            tracing::debug!("running adb {args:?}");
+            let output = get_adb().args(&args).stdin(Stdio::null()).output().await?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr = stderr.trim().trim_start_matches("adb: error: ");
+                return Err(match Error::from_stderr(stderr) {
+                    Some(err) => tokio::io::Error::other(err),
+                    None if stderr.is_empty() => {
+                        // name the subcommand rather than the -s in front of it
+                        let command = match &args[..] {
+                            [s, _, command, ..] if s == "-s" => command,
+                            _ => &args[0],
+                        };
+                        tokio::io::Error::other(format!("adb {command} failed: {}", output.status))
+                    }
+                    None => tokio::io::Error::other(stderr.to_string()),
+                });
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+    }
+
+    fn host_service(&self, service: &str) -> BoxFuture<'static, tokio::io::Result<Vec<u8>>> {
+        let service = service.to_string();
+        Box::pin(async move {
+            let port = std::env::var("ANDROID_ADB_SERVER_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(5037);
+            let mut server = TcpStream::connect(("127.0.0.1", port)).await?;
+
+            // requests and responses are prefixed with their length as 4 hex digits
+            server
+                .write_all(format!("{:04x}{service}", service.len()).as_bytes())
+                .await?;
+
+            let mut status = [0; 4];
+            server.read_exact(&mut status).await?;
+            if &status != b"OKAY" {
+                return Err(tokio::io::Error::other(format!(
+                    "adb server refused {service}: {}",
+                    String::from_utf8_lossy(&status)
+                )));
+            }
+
+            let mut len = [0; 4];
+            server.read_exact(&mut len).await?;
+            let len = std::str::from_utf8(&len)
+                .ok()
+                .and_then(|len| usize::from_str_radix(len, 16).ok())
+                .ok_or_else(|| tokio::io::Error::from(tokio::io::ErrorKind::InvalidData))?;
+
+            let mut response = vec![0; len];
+            server.read_exact(&mut response).await?;
+            Ok(response)
+        })
+    }
+}
+
+/// Prints each adb invocation and pretends it wrote nothing
+pub struct DryRunAdb;
+
+impl AdbTransport for DryRunAdb {
+    fn run(&self, args: &[String]) -> tokio::io::Result<AdbOutput> {
+        print_command("adb", args);
+        Ok(Box::pin(tokio::io::empty()))
+    }
+}
+
+/// Replays canned output for known adb invocations
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockAdb {
+    outputs: std::collections::HashMap<Vec<String>, Vec<u8>>,
+    host_services: std::collections::HashMap<String, Vec<u8>>,
+}
+
+#[cfg(test)]
+impl MockAdb {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Makes `adb <args>` write `output` to stdout
+    pub fn with_output(mut self, args: &str, output: impl Into<Vec<u8>>) -> Self {
+        self.outputs
+            .insert(shell_words::split(args).unwrap(), output.into());
+        self
+    }
+
+    /// Makes the adb server answer `service` with `response`
+    pub fn with_host_service(mut self, service: &str, response: impl Into<Vec<u8>>) -> Self {
+        self.host_services
+            .insert(service.to_string(), response.into());
+        self
+    }
+}
+
+#[cfg(test)]
+impl AdbTransport for MockAdb {
+    fn run(&self, args: &[String]) -> tokio::io::Result<AdbOutput> {
+        match self.outputs.get(args) {
+            Some(output) => Ok(Box::pin(std::io::Cursor::new(output.clone()))),
+            None => Err(tokio::io::Error::new(
+                tokio::io::ErrorKind::NotFound,
+                format!("no canned output for `adb {}`", args.join(" ")),
+            )),
+        }
+    }
+
+    fn host_service(&self, service: &str) -> BoxFuture<'static, tokio::io::Result<Vec<u8>>> {
+        let response = self.host_services.get(service).cloned().ok_or_else(|| {
+            tokio::io::Error::new(
+                tokio::io::ErrorKind::NotFound,
+                format!("no canned response for {service}"),
+            )
+        });
+        Box::pin(async move { response })
+    }
+}
+
+/// The transport used outside of tests
+pub fn transport() -> &'static dyn AdbTransport {
+    if is_dry_run() {
+        &DryRunAdb
+    } else {
+        &RealAdb
+    }
+}
+
+/// Runs `adb shell <command>` against `serial`, or adb's default device if `None`.
+pub fn shell(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+    command: &str,
+) -> impl Stream<Item = tokio::io::Result<String>> {
+    // a malformed command or missing adb is reported through the stream, since the command may
+    // have been typed in by hand
+    let stdout = shell_words::split(command)
+        .map_err(|err| tokio::io::Error::new(tokio::io::ErrorKind::InvalidInput, err))
+        .and_then(|command| {
+            let mut args = Vec::new();
+            if let Some(serial) = serial {
+                args.push("-s".to_string());
+                args.push(serial.to_string());
+            }
+            args.push("shell".to_string());
+            args.extend(command);
+            transport.run(&args)
+        });
 
     try_stream! {
+        let mut lines = BufReader::new(stdout?).lines();
         loop {
             match lines.next_line().await? {
-                Some(line) => yield line,
+                // adb on some platforms emits "\r\n" line endings
+                Some(mut line) => {
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                    yield line
+                }
                 None => break,
             }
         }
     }
 }
 
-pub async fn devices() -> Vec<Result<AdbDevice, crate::devices::Error>> {
-    track_devices().next().await.unwrap().unwrap()
+/// Reads system properties from a device, giving `None` for any that aren't set
+pub async fn getprop(
+    transport: &dyn AdbTransport,
+    serial: &str,
+    names: &[&str],
+) -> tokio::io::Result<Vec<Option<String>>> {
+    // one getprop per property in a single shell, so each prints exactly one line
+    let command = names
+        .iter()
+        .map(|name| format!("getprop {name}"))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let output = shell(transport, Some(serial), &command);
+    tokio::pin!(output);
+
+    let mut values = Vec::new();
+    while let Some(line) = output.next().await.transpose()? {
+        values.push(Some(line).filter(|value| !value.is_empty()));
+    }
+    values.resize(names.len(), None);
+    Ok(values)
+}
+
+/// How long a device has been running since it booted, from `/proc/uptime`
+pub async fn uptime(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+) -> tokio::io::Result<Duration> {
+    let output = shell(transport, serial, "cat /proc/uptime");
+    tokio::pin!(output);
+
+    // the first field is seconds since boot, the second time spent idle
+    let line = output.next().await.transpose()?.unwrap_or_default();
+    line.split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| {
+            tokio::io::Error::new(
+                tokio::io::ErrorKind::InvalidData,
+                format!("unexpected /proc/uptime: '{line}'"),
+            )
+        })
+}
+
+// runs `adb <args>` and expects a line of its output to start with one of `success`, returning
+// that line. adb reports these failures on stdout and still exits successfully
+async fn run_expecting(
+    transport: &dyn AdbTransport,
+    args: &[&str],
+    success: &[&str],
+) -> tokio::io::Result<String> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let mut output = String::new();
+    transport.run(&args)?.read_to_string(&mut output).await?;
+
+    let output = output.trim();
+    let confirmation = output
+        .lines()
+        .find(|line| success.iter().any(|success| line.starts_with(success)));
+    match confirmation {
+        Some(line) => Ok(line.to_string()),
+        None if output.is_empty() => {
+            Err(tokio::io::Error::other(format!("adb {} failed", args[0])))
+        }
+        None => Err(tokio::io::Error::other(output.to_string())),
+    }
+}
+
+/// Pairs with a device for wireless debugging, using the address and 6 digit code from its
+/// "Pair device with pairing code" dialog. Returns adb's confirmation.
+pub async fn pair(
+    transport: &dyn AdbTransport,
+    addr: &str,
+    code: &str,
+) -> tokio::io::Result<String> {
+    run_expecting(transport, &["pair", addr, code], &["Successfully paired"]).await
+}
+
+/// Connects to a device over the network, e.g. once it's been paired. Returns adb's confirmation.
+pub async fn connect(transport: &dyn AdbTransport, addr: &str) -> tokio::io::Result<String> {
+    run_expecting(
+        transport,
+        &["connect", addr],
+        &["connected to", "already connected to"],
+    )
+    .await
+}
+
+/// The name of the process `pid` belongs to on a device, usually the package name for apps. `None`
+/// if no such process is running.
+pub async fn process_name(
+    transport: &dyn AdbTransport,
+    serial: &str,
+    pid: i32,
+) -> tokio::io::Result<Option<String>> {
+    let output = shell(transport, Some(serial), &format!("ps -o NAME= -p {pid}"));
+    tokio::pin!(output);
+
+    let name = output.next().await.transpose()?;
+    Ok(name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty()))
+}
+
+/// Sends a key press like `KEYCODE_POWER` to a device, as if its button had been pressed
+pub async fn send_keyevent(
+    transport: &dyn AdbTransport,
+    serial: &str,
+    keycode: &str,
+) -> tokio::io::Result<()> {
+    let output = shell(
+        transport,
+        Some(serial),
+        &format!("input keyevent {keycode}"),
+    );
+    tokio::pin!(output);
+    while output.next().await.transpose()?.is_some() {}
+    Ok(())
+}
+
+/// Installs an OTA package on a device waiting in sideload mode, printing adb's summary once done
+pub async fn sideload(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+    package: &Path,
+) -> tokio::io::Result<()> {
+    let mut args = Vec::new();
+    if let Some(serial) = serial {
+        args.extend(["-s".to_string(), serial.to_string()]);
+    }
+    args.extend(["sideload".to_string(), package.display().to_string()]);
+
+    let output = transport.run_checked(&args).await?;
+    if !output.is_empty() {
+        println!("{output}");
+    }
+    Ok(())
+}
+
+/// A port forwarded by `adb forward` or `adb reverse`, as listed by their `--list`
+#[derive(Debug, Clone)]
+pub struct PortForward {
+    /// The device for `adb forward`. `adb reverse` lists the name of the host's side instead.
+    pub serial: String,
+    pub local: String,
+    pub remote: String,
+}
+
+impl std::fmt::Display for PortForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.local, self.remote)
+    }
+}
+
+/// Parses a socket for `adb forward` and `adb reverse` like `tcp:8080` or
+/// `localabstract:chrome_devtools_remote`, taking a bare port number to mean tcp
+pub fn parse_socket(socket: &str) -> Result<String, String> {
+    if socket.parse::<u16>().is_ok() {
+        return Ok(format!("tcp:{socket}"));
+    }
+    match socket.split_once(':') {
+        Some((kind, name)) if !kind.is_empty() && !name.is_empty() => Ok(socket.to_string()),
+        _ => Err(format!("'{socket}' isn't a port or of the form KIND:NAME")),
+    }
+}
+
+/// Parses the `serial local remote` lines of `adb forward --list` and `adb reverse --list`
+fn parse_forwards(output: &str) -> Vec<PortForward> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(PortForward {
+                serial: fields.next()?.to_string(),
+                local: fields.next()?.to_string(),
+                remote: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+// runs `adb <args>` against `serial`, or adb's default device if `None`, with
+// `AdbTransport::run_checked`
+async fn run_checked(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+    args: &[&str],
+) -> tokio::io::Result<String> {
+    let mut full_args = Vec::new();
+    if let Some(serial) = serial {
+        full_args.extend(["-s".to_string(), serial.to_string()]);
+    }
+    full_args.extend(args.iter().map(|arg| arg.to_string()));
+    transport.run_checked(&full_args).await
+}
+
+/// Forwards `local` on this computer to `remote` on a device, e.g. `tcp:8080`. Returns the port adb
+/// picked if `local` was `tcp:0`.
+pub async fn forward(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+    local: &str,
+    remote: &str,
+) -> tokio::io::Result<Option<String>> {
+    let port = run_checked(transport, serial, &["forward", local, remote]).await?;
+    Ok(Some(port).filter(|port| !port.is_empty()))
+}
+
+/// Forwards `remote` on a device back to `local` on this computer, e.g. `tcp:8081`. Returns the
+/// port adb picked if `remote` was `tcp:0`.
+pub async fn reverse(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+    remote: &str,
+    local: &str,
+) -> tokio::io::Result<Option<String>> {
+    let port = run_checked(transport, serial, &["reverse", remote, local]).await?;
+    Ok(Some(port).filter(|port| !port.is_empty()))
+}
+
+/// Ports forwarded with `adb forward`, for every device
+pub async fn forward_list(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+) -> tokio::io::Result<Vec<PortForward>> {
+    Ok(parse_forwards(
+        &run_checked(transport, serial, &["forward", "--list"]).await?,
+    ))
+}
+
+/// Ports forwarded with `adb reverse` on one device
+pub async fn reverse_list(
+    transport: &dyn AdbTransport,
+    serial: Option<&str>,
+) -> tokio::io::Result<Vec<PortForward>> {
+    Ok(parse_forwards(
+        &run_checked(transport, serial, &["reverse", "--list"]).await?,
+    ))
+}
+
+/// Lists devices by asking an already running adb server directly over its socket. This is much
+/// quicker than spawning adb, but fails rather than starting the server.
+pub async fn server_devices(transport: &dyn AdbTransport) -> tokio::io::Result<Vec<AdbDevice>> {
+    let devices = transport.host_service("host:devices").await?;
+    Ok(String::from_utf8_lossy(&devices)
+        .lines()
+        .filter_map(|line| AdbDevice::parse(line).ok())
+        .collect())
+}
+
+pub async fn devices(
+    transport: &dyn AdbTransport,
+) -> Vec<Result<AdbDevice, crate::devices::Error>> {
+    // the first message from track-devices is the current device list
+    match Box::pin(track_devices(transport)).next().await {
+        Some(Ok(devices)) => devices,
+        Some(Err(err)) => {
+            tracing::debug!("track-devices failed: {err:?}");
+            Vec::new()
+        }
+        None => Vec::new(),
+    }
+}
+
+const SPAWN_RETRIES: u32 = 4;
+const SPAWN_MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Runs adb, retrying with exponential backoff if it fails to start. Starting can fail briefly
+/// while adb is being replaced by an SDK update, or when the system is short on processes.
+pub async fn run_with_retry(
+    transport: &dyn AdbTransport,
+    args: &[String],
+) -> tokio::io::Result<AdbOutput> {
+    let mut backoff = SPAWN_MIN_BACKOFF;
+    let mut retries = 0;
+    loop {
+        match transport.run(args) {
+            Ok(output) => return Ok(output),
+            // a missing adb isn't going to show up
+            Err(err) if err.kind() != tokio::io::ErrorKind::NotFound && retries < SPAWN_RETRIES => {
+                tracing::debug!("failed to run adb, retrying in {backoff:?}: {err:?}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                retries += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
 }
 
-pub fn track_devices() -> impl Stream<
+/// Streams the device list each time it changes. Failing to run adb is reported as the only item.
+pub fn track_devices(
+    transport: &dyn AdbTransport,
+) -> impl Stream<
     Item = Result<
         Vec<Result<AdbDevice, crate::devices::Error>>,
         crate::devices::TrackDevicesDecodeError,
     >,
-> {
-    let track_devices = get_adb()
-        .args(shell_words::split("track-devices -l").unwrap().as_slice())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    let device_state_stream = FramedRead::new(
-        BufReader::new(track_devices.stdout.unwrap()),
-        crate::devices::TrackDevicesDecoder::new(),
-    );
+> + '_ {
+    stream! {
+        let track_devices =
+            match run_with_retry(transport, &shell_words::split("track-devices -l").unwrap()).await
+            {
+                Ok(track_devices) => track_devices,
+                Err(err) => {
+                    yield Err(err.into());
+                    return;
+                }
+            };
 
-    device_state_stream
+        // decode errors are passed along rather than ending the stream, since the next message
+        // can still be read
+        let mut device_state_stream = FramedRead::new(
+            BufReader::new(track_devices),
+            crate::devices::TrackDevicesDecoder::new(),
+        );
+        while let Some(devices) = device_state_stream.next().await {
+            yield devices;
+        }
+    }
 }
 
 pub use logcat::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERIAL: &str = "emulator-5554";
+
+    #[tokio::test]
+    async fn shell_strips_crlf_line_endings() {
+        let adb = MockAdb::new().with_output(
+            "-s emulator-5554 shell ls /sdcard",
+            "Alarms\r\nDCIM\r\n\r\nDownload\n",
+        );
+
+        let lines: Vec<_> = shell(&adb, Some(SERIAL), "ls /sdcard")
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(lines, ["Alarms", "DCIM", "", "Download"]);
+    }
+
+    #[tokio::test]
+    async fn getprop_gives_none_for_unset_properties() {
+        let adb = MockAdb::new().with_output(
+            "-s emulator-5554 shell getprop ro.product.name; getprop ro.serialno; getprop \
+             ro.build.version.release",
+            "sdk_gphone64_x86_64\n\n14\n",
+        );
+
+        let values = getprop(
+            &adb,
+            SERIAL,
+            &["ro.product.name", "ro.serialno", "ro.build.version.release"],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            values,
+            [
+                Some("sdk_gphone64_x86_64".to_string()),
+                None,
+                Some("14".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn uptime_reads_seconds_since_boot() {
+        let adb = MockAdb::new().with_output(
+            "-s emulator-5554 shell cat /proc/uptime",
+            "3723.51 12500.02\n",
+        );
+
+        let uptime = uptime(&adb, Some(SERIAL)).await.unwrap();
+        assert_eq!(uptime, Duration::from_secs_f64(3723.51));
+    }
+
+    #[tokio::test]
+    async fn server_devices_parses_the_host_devices_response() {
+        let adb = MockAdb::new().with_host_service(
+            "host:devices",
+            "emulator-5554\tdevice\nR58M123ABC\toffline\n",
+        );
+
+        let devices = server_devices(&adb).await.unwrap();
+        let devices: Vec<_> = devices
+            .iter()
+            .map(|device| {
+                (
+                    device.connection_name.as_str(),
+                    device.properties.connection_state.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(devices, [(SERIAL, "device"), ("R58M123ABC", "offline")]);
+    }
+
+    #[tokio::test]
+    async fn forward_list_parses_each_forward() {
+        let adb = MockAdb::new().with_output(
+            "forward --list",
+            "emulator-5554 tcp:8080 tcp:8080\nR58M123ABC tcp:9222 localabstract:chrome_devtools_remote\n",
+        );
+
+        let forwards = forward_list(&adb, None).await.unwrap();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!(forwards[1].serial, "R58M123ABC");
+        assert_eq!(forwards[1].local, "tcp:9222");
+        assert_eq!(forwards[1].remote, "localabstract:chrome_devtools_remote");
+    }
+
+    #[tokio::test]
+    async fn devices_reads_the_first_track_devices_list() {
+        let list = "emulator-5554\tdevice product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 \
+                    device:emu64x transport_id:1\n\
+                    R58M123ABC\tunauthorized usb:1-1 transport_id:2\n";
+        let adb =
+            MockAdb::new().with_output("track-devices -l", format!("{:04x}{list}", list.len()));
+
+        let devices: Vec<_> = devices(&adb)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].connection_name, SERIAL);
+        assert_eq!(devices[0].properties.connection_state, "device");
+        assert_eq!(
+            devices[0].properties.live.as_ref().unwrap().product,
+            "sdk_gphone64_x86_64"
+        );
+        assert_eq!(devices[1].connection_name, "R58M123ABC");
+        assert_eq!(devices[1].properties.connection_state, "unauthorized");
+    }
+
+    // a logger_entry_v4 from the main buffer, logged `sec` seconds into the epoch
+    fn main_log_entry(sec: u32, message: &str) -> Vec<u8> {
+        let payload = [&[4][..], b"Tag\0", message.as_bytes(), b"\0"].concat();
+        let header = [
+            (payload.len() as u16).to_le_bytes().to_vec(),
+            28u16.to_le_bytes().to_vec(),
+            // pid, tid, sec, nsec, lid and uid
+            [100, 101, sec, 0, 0, 1000]
+                .iter()
+                .flat_map(|field: &u32| field.to_le_bytes())
+                .collect(),
+        ]
+        .concat();
+        [header, payload].concat()
+    }
+
+    #[tokio::test]
+    async fn logcat_marks_the_beginning_of_each_buffer_once() {
+        // logging clients set the timestamps, so they can go backwards without anything being
+        // cleared
+        let output = [
+            main_log_entry(100, "first"),
+            main_log_entry(101, "second"),
+            main_log_entry(50, "out of order"),
+        ]
+        .concat();
+        let adb = MockAdb::new().with_output("-s emulator-5554 logcat -B", output);
+
+        let messages: Vec<_> = logcat(&adb, SERIAL, &LogcatOptions::default())
+            .map(|message| match message.unwrap().buffer {
+                LogBuffer::TextLog(buffer) => buffer.message,
+                LogBuffer::Marker(text) => format!("--- {text}"),
+                _ => unreachable!(),
+            })
+            .collect()
+            .await;
+        assert_eq!(
+            messages,
+            ["--- beginning of main", "first", "second", "out of order"]
+        );
+    }
+
+    #[tokio::test]
+    async fn logcat_merged_tags_each_message_with_its_serial() {
+        let adb = MockAdb::new()
+            .with_output(
+                "-s emulator-5554 logcat -B",
+                [main_log_entry(100, "a1"), main_log_entry(101, "a2")].concat(),
+            )
+            .with_output(
+                "-s R58M123ABC logcat -B",
+                [main_log_entry(100, "b1"), main_log_entry(101, "b2")].concat(),
+            );
+
+        let messages: Vec<_> =
+            logcat_merged(&adb, &[SERIAL, "R58M123ABC"], &LogcatOptions::default())
+                .filter_map(|(serial, message)| match message.buffer {
+                    LogBuffer::TextLog(buffer) => Some((serial, buffer.message)),
+                    _ => None,
+                })
+                .collect()
+                .await;
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|(serial, message)| (serial.as_str(), message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (SERIAL, "a1"),
+                ("R58M123ABC", "b1"),
+                (SERIAL, "a2"),
+                ("R58M123ABC", "b2")
+            ]
+        );
+    }
+
+    #[test]
+    fn format_message_keeps_the_prefix_of_empty_messages() {
+        let message = LogMessage {
+            timestamp: chrono::NaiveDate::from_ymd_opt(2023, 10, 16)
+                .unwrap()
+                .and_hms_milli_opt(10, 49, 3, 123)
+                .unwrap(),
+            pid: 1234,
+            tid: 5678,
+            lid: Some(0),
+            uid: None,
+            buffer: LogBuffer::TextLog(TextLogBuffer {
+                level: LogLevel::Debug,
+                tag: "Tag".to_string(),
+                message: String::new(),
+            }),
+            raw: None,
+        };
+
+        assert_eq!(format_message(&message, LogFormat::Brief), "D/Tag( 1234): ");
+        assert_eq!(
+            format_message(&message, LogFormat::Threadtime),
+            "10-16 10:49:03.123  1234  5678 D Tag: "
+        );
+        assert_eq!(format_message(&message, LogFormat::Tag), "D/Tag: ");
+    }
+}
@@ -1,12 +1,15 @@
-use std::process::Stdio;
+use std::{borrow::Cow, collections::HashSet};
 
 use bytes::{Buf, BytesMut};
 use chrono::{prelude::*, DateTime};
 use futures::Stream;
 use quick_error::quick_error;
 use tokio::io::BufReader;
+use tokio_stream::StreamExt;
 use tokio_util::codec::FramedRead;
 
+use super::AdbTransport;
+
 const EXPECTED_BEGINNING_OF_BUFFER: &[u8] = b"--------- beginning of ";
 const EXPECTED_BEGINNING_OF_LOG_HEADER: &[u8] = b"[ ";
 
@@ -23,21 +26,57 @@ const LOG_LEVEL_FATAL: u8 = 7;
 #[allow(unused)]
 const LOG_LEVEL_SILENT: u8 = 8;
 
-#[allow(unused)]
 const LOG_ID_MAIN: u32 = 0;
-#[allow(unused)]
 const LOG_ID_RADIO: u32 = 1;
 const LOG_ID_EVENTS: u32 = 2;
-#[allow(unused)]
 const LOG_ID_SYSTEM: u32 = 3;
-#[allow(unused)]
 const LOG_ID_CRASH: u32 = 4;
 const LOG_ID_STATS: u32 = 5;
 const LOG_ID_SECURITY: u32 = 6;
-#[allow(unused)]
 const LOG_ID_KERNEL: u32 = 7;
 
-#[derive(Debug, Copy, Clone)]
+/// Name of the buffer for a log id, as accepted by `adb logcat -b`
+pub fn log_id_name(lid: u32) -> Option<&'static str> {
+    match lid {
+        LOG_ID_MAIN => Some("main"),
+        LOG_ID_RADIO => Some("radio"),
+        LOG_ID_EVENTS => Some("events"),
+        LOG_ID_SYSTEM => Some("system"),
+        LOG_ID_CRASH => Some("crash"),
+        LOG_ID_STATS => Some("stats"),
+        LOG_ID_SECURITY => Some("security"),
+        LOG_ID_KERNEL => Some("kernel"),
+        _ => None,
+    }
+}
+
+/// Parses a buffer name as accepted by `adb logcat -b` into its log id
+pub fn parse_log_id(name: &str) -> Result<u32, String> {
+    (LOG_ID_MAIN..=LOG_ID_KERNEL)
+        .find(|lid| log_id_name(*lid) == Some(name))
+        .ok_or_else(|| format!("unknown buffer '{name}'"))
+}
+
+/// Checks a filterspec like `ActivityManager:I *:S` is made up of `TAG:LEVEL` pairs, for passing
+/// on to adb as is
+pub fn parse_filterspec(filterspec: &str) -> Result<String, String> {
+    let specs = shell_words::split(filterspec).map_err(|err| err.to_string())?;
+    if specs.is_empty() {
+        return Err("filterspec is empty".to_string());
+    }
+
+    for spec in &specs {
+        match spec.rsplit_once(':') {
+            Some((tag, level))
+                if !tag.is_empty() && matches!(level, "V" | "D" | "I" | "W" | "E" | "F" | "S") => {}
+            _ => return Err(format!("'{spec}' isn't of the form TAG:LEVEL")),
+        }
+    }
+    Ok(filterspec.to_string())
+}
+
+/// Ordered by severity. Priorities logcat doesn't name sort below all the others.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LogLevel {
     Other(u8),
     Verbose,
@@ -48,6 +87,47 @@ pub enum LogLevel {
     Fatal,
 }
 
+impl LogLevel {
+    /// The priority letter used by `adb logcat` output and filterspecs
+    pub fn as_char(&self) -> char {
+        match self {
+            LogLevel::Other(x) => *x as char,
+            LogLevel::Verbose => 'V',
+            LogLevel::Debug => 'D',
+            LogLevel::Info => 'I',
+            LogLevel::Warning => 'W',
+            LogLevel::Error => 'E',
+            LogLevel::Fatal => 'F',
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "v" | "verbose" => Ok(LogLevel::Verbose),
+            "d" | "debug" => Ok(LogLevel::Debug),
+            "i" | "info" => Ok(LogLevel::Info),
+            "w" | "warn" | "warning" => Ok(LogLevel::Warning),
+            "e" | "error" => Ok(LogLevel::Error),
+            "f" | "fatal" => Ok(LogLevel::Fatal),
+            _ => Err(format!(
+                "unknown log level '{s}', expected one of V, D, I, W, E, F"
+            )),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for LogLevel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug)]
 pub struct LogLongMessage {
     pub timestamp: DateTime<chrono::FixedOffset>,
@@ -66,8 +146,36 @@ pub struct TextLogBuffer {
     pub message: String,
 }
 
+/// The buffers whose entries are binary events rather than text
+#[derive(Debug, Copy, Clone)]
+pub enum BinaryLogId {
+    Events,
+    Stats,
+    Security,
+}
+
+impl BinaryLogId {
+    fn from_log_id(lid: u32) -> Option<Self> {
+        match lid {
+            LOG_ID_EVENTS => Some(BinaryLogId::Events),
+            LOG_ID_STATS => Some(BinaryLogId::Stats),
+            LOG_ID_SECURITY => Some(BinaryLogId::Security),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryLogId::Events => "events",
+            BinaryLogId::Stats => "stats",
+            BinaryLogId::Security => "security",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BinaryLogBuffer {
+    pub log_id: BinaryLogId,
     pub tag: i32,
 }
 
@@ -75,6 +183,12 @@ pub struct BinaryLogBuffer {
 pub enum LogBuffer {
     TextLog(TextLogBuffer),
     BinaryLog(BinaryLogBuffer),
+    /// Raw payload of an entry that could not be decoded
+    Unknown(Vec<u8>),
+    /// Synthetic divider, e.g. "beginning of main" like `adb logcat` prints
+    Marker(String),
+    /// Full-width divider inserted by xadb itself, e.g. where the session started
+    Divider(String),
 }
 
 #[derive(Debug, Clone)]
@@ -85,6 +199,120 @@ pub struct LogMessage {
     pub lid: Option<u32>,
     pub uid: Option<u32>,
     pub buffer: LogBuffer,
+    /// The message exactly as it was read, before any lossy utf-8 conversion. Only kept when
+    /// `LogcatOptions::keep_raw` is set.
+    pub raw: Option<Vec<u8>>,
+}
+
+impl LogMessage {
+    /// The original bytes of the message, falling back to the decoded text if they weren't kept
+    pub fn raw_message(&self) -> &[u8] {
+        if let Some(raw) = &self.raw {
+            return raw;
+        }
+
+        match &self.buffer {
+            LogBuffer::TextLog(buffer) => buffer.message.as_bytes(),
+            LogBuffer::BinaryLog(_) => &[],
+            LogBuffer::Unknown(bytes) => bytes,
+            LogBuffer::Marker(text) | LogBuffer::Divider(text) => text.as_bytes(),
+        }
+    }
+
+    /// How many messages logd says it dropped, if this is one of its `chatty` notices. logd
+    /// evicts messages when a buffer fills before they're read, and collapses identical ones.
+    pub fn dropped_count(&self) -> Option<usize> {
+        lazy_static::lazy_static! {
+            static ref RE: regex::Regex =
+                regex::Regex::new(r"\b(?:expire|identical) (?P<count>[[:digit:]]+) lines?").unwrap();
+        }
+
+        match &self.buffer {
+            LogBuffer::TextLog(buffer) if buffer.tag == "chatty" => {
+                RE.captures(&buffer.message)?["count"].parse().ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The `adb logcat -v` formats messages can be written out in
+#[derive(clap::ValueEnum, Copy, Clone, Default)]
+pub enum LogFormat {
+    /// `D/Tag( 1234): message`
+    Brief,
+    /// `10-16 10:49:03.123  1234  5678 D Tag: message`
+    #[default]
+    Threadtime,
+    /// A header line with the time, ids, level and tag, then the message and a blank line
+    Long,
+    /// `D/Tag: message`
+    Tag,
+    /// Only the message, with the bytes exactly as they were logged
+    Raw,
+}
+
+/// Writes a message the way `adb logcat -v <format>` would, so the output can be compared with or
+/// replace a real capture. Each line of a multi-line message gets its own prefix, except in the
+/// long format.
+pub fn format_message(message: &LogMessage, format: LogFormat) -> String {
+    let (level, tag, text) = match &message.buffer {
+        LogBuffer::TextLog(buffer) => (
+            buffer.level.as_char(),
+            buffer.tag.clone(),
+            buffer.message.clone(),
+        ),
+        LogBuffer::BinaryLog(buffer) => (
+            'I',
+            String::new(),
+            format!("<binary event tag {}>", buffer.tag),
+        ),
+        LogBuffer::Unknown(bytes) => ('?', String::new(), format!("<{} bytes>", bytes.len())),
+        LogBuffer::Marker(text) | LogBuffer::Divider(text) => return format!("--------- {text}"),
+    };
+
+    let time = message.timestamp.format("%m-%d %H:%M:%S%.3f");
+    // an empty message still gets a line with the prefix, like adb prints
+    let lines: Vec<&str> = if text.is_empty() {
+        vec![""]
+    } else {
+        text.lines().collect()
+    };
+    match format {
+        LogFormat::Brief => lines
+            .iter()
+            .map(|line| format!("{level}/{tag}({:>5}): {line}", message.pid))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        LogFormat::Threadtime => lines
+            .iter()
+            .map(|line| {
+                format!(
+                    "{time} {:>5} {:>5} {level} {tag}: {line}",
+                    message.pid, message.tid
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        LogFormat::Long => format!(
+            "[ {time} {:>5}:{:>5} {level}/{tag} ]\n{text}\n",
+            message.pid, message.tid
+        ),
+        LogFormat::Tag => lines
+            .iter()
+            .map(|line| format!("{level}/{tag}: {line}"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        LogFormat::Raw => String::from_utf8_lossy(message.raw_message()).into_owned(),
+    }
+}
+
+/// Like `format_message`, but the raw format keeps bytes that aren't valid utf-8
+pub fn format_message_bytes(message: &LogMessage, format: LogFormat) -> Cow<'_, [u8]> {
+    match format {
+        LogFormat::Raw => Cow::from(message.raw_message()),
+        _ => Cow::from(format_message(message, format).into_bytes()),
+    }
 }
 
 #[derive(Debug)]
@@ -103,6 +331,16 @@ quick_error! {
     }
 }
 
+impl LogcatDecodeError {
+    /// Whether the log ended because the device went offline, rather than anything being wrong
+    /// with what was read
+    pub fn is_device_offline(&self) -> bool {
+        match self {
+            LogcatDecodeError::Io(err) => super::is_device_offline(err),
+        }
+    }
+}
+
 #[allow(unused)]
 struct LogcatStringDecoder {
     is_in_error_state: bool,
@@ -431,30 +669,129 @@ impl tokio_util::codec::Decoder for LogcatStringDecoder {
     }
 }
 
-pub fn logcat(serial: &str) -> impl Stream<Item = Result<LogMessage, LogcatDecodeError>> {
+/// Options controlling what `adb logcat` sends back
+#[derive(Clone, Default)]
+pub struct LogcatOptions {
+    /// Only read the text buffers, so binary events/stats/security entries never arrive
+    pub text_only: bool,
+    /// Passed to adb as `*:<level>`, so lower priority messages never leave the device. This is a
+    /// hard floor - filtering in the UI can only narrow what is received further.
+    pub min_level: Option<LogLevel>,
+    /// Passed to adb as `-b`, so only these log ids are read instead of adb's default buffers.
+    /// Takes precedence over `text_only`.
+    pub log_ids: Vec<u32>,
+    /// Passed to adb as `--pid=<pid>`, so only these processes' messages are sent
+    pub pids: Vec<u32>,
+    /// `TAG:LEVEL` filterspecs passed to adb after `min_level`, so they can override it
+    pub filterspecs: Vec<String>,
+    /// Keep each message's original bytes alongside the decoded text, see
+    /// `LogMessage::raw_message`
+    pub keep_raw: bool,
+    /// Insert a "switch to <buffer>" marker wherever consecutive entries come from different
+    /// buffers, like `adb logcat -D`. The binary format has no dividers of its own, so they're
+    /// made up while decoding.
+    pub dividers: bool,
+    /// Remove ANSI escape sequences and other control characters from messages, see
+    /// `strip_control`
+    pub strip_ansi: bool,
+    /// Bytes read from adb at a time, `DEFAULT_READ_BUFFER_SIZE` if not set
+    pub read_buffer_size: Option<usize>,
+}
+
+/// Enough for a few hundred typical entries, so a chatty device is read in large chunks
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 64 * 1024;
+
+impl LogcatOptions {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["logcat".to_string(), "-B".to_string()];
+        if !self.log_ids.is_empty() {
+            args.push("-b".to_string());
+            args.push(
+                self.log_ids
+                    .iter()
+                    .filter_map(|lid| log_id_name(*lid))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        } else if self.text_only {
+            args.push("-b".to_string());
+            args.push("main,system,crash".to_string());
+        }
+        for pid in &self.pids {
+            args.push(format!("--pid={pid}"));
+        }
+        // filterspecs have to come after the options
+        if let Some(level) = self.min_level {
+            args.push(format!("*:{}", level.as_char()));
+        }
+        args.extend(self.filterspecs.iter().cloned());
+        args
+    }
+}
+
+pub fn logcat(
+    transport: &dyn AdbTransport,
+    serial: &str,
+    options: &LogcatOptions,
+) -> impl Stream<Item = Result<LogMessage, LogcatDecodeError>> {
     assert!(!serial.is_empty());
 
-    let adb = super::get_adb()
-        .arg("-s")
-        .arg(serial)
-        .args(shell_words::split("logcat -B").unwrap().as_slice())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    FramedRead::new(
-        BufReader::new(adb.stdout.unwrap()),
-        LogcatBinaryDecoder::new(),
+    let mut args = vec!["-s".to_string(), serial.to_string()];
+    args.extend(options.args());
+
+    let adb = transport.run(&args).unwrap();
+
+    let capacity = options.read_buffer_size.unwrap_or(DEFAULT_READ_BUFFER_SIZE);
+    FramedRead::with_capacity(
+        BufReader::with_capacity(capacity, adb),
+        LogcatBinaryDecoder::new(options),
+        capacity,
     )
 }
 
-struct LogcatBinaryDecoder;
+/// Interleaves the logs of several devices into one stream, tagging each message with the serial
+/// it came from. Messages are yielded in arrival order rather than by timestamp so nothing has to
+/// be buffered.
+// for a multi-device view or exporter; nothing reads more than one device's log yet
+#[allow(unused)]
+pub fn logcat_merged(
+    transport: &dyn AdbTransport,
+    serials: &[&str],
+    options: &LogcatOptions,
+) -> impl Stream<Item = (String, LogMessage)> {
+    futures::stream::select_all(serials.iter().map(|serial| {
+        let serial = serial.to_string();
+        Box::pin(
+            logcat(transport, &serial, options).filter_map(move |message| match message {
+                Ok(message) => Some((serial.clone(), message)),
+                Err(err) => {
+                    tracing::debug!("logcat decode error from {serial}: {err:?}");
+                    None
+                }
+            }),
+        )
+    }))
+}
+
+struct LogcatBinaryDecoder {
+    // log ids we've already emitted a "beginning of" marker for
+    seen_log_ids: HashSet<u32>,
+    // log id of the last entry decoded, to tell when to emit a "switch to" marker
+    last_log_id: Option<u32>,
+    keep_raw: bool,
+    dividers: bool,
+    strip_ansi: bool,
+}
 
 impl LogcatBinaryDecoder {
-    fn new() -> Self {
-        Self
+    fn new(options: &LogcatOptions) -> Self {
+        Self {
+            seen_log_ids: HashSet::new(),
+            last_log_id: None,
+            keep_raw: options.keep_raw,
+            dividers: options.dividers,
+            strip_ansi: options.strip_ansi,
+        }
     }
 }
 
@@ -511,6 +848,95 @@ fn read_u32(src: &BytesMut, hdr_size: usize, off: usize) -> Option<u32> {
     ]))
 }
 
+/// Removes ANSI escape sequences, like the colors some apps log, and any other control characters
+/// apart from newlines and tabs, which would otherwise garble the terminal
+pub fn strip_control(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' | '\t' => stripped.push(c),
+            '\x1b' => match chars.next() {
+                // CSI, e.g. colors: parameters and intermediates up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, e.g. titles and hyperlinks: up to BEL or ST (ESC \)
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // two character sequences like ESC c
+                _ => {}
+            },
+            c if c.is_control() => {}
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+// decodes a `<level><tag>\0<message>\0` payload, or returns None if the tag is never terminated.
+// also returns the undecoded message bytes.
+fn decode_text_log(buf: &[u8]) -> Option<(TextLogBuffer, &[u8])> {
+    let level = match buf[0] {
+        LOG_LEVEL_VERBOSE => LogLevel::Verbose,
+        LOG_LEVEL_DEBUG => LogLevel::Debug,
+        LOG_LEVEL_INFO => LogLevel::Info,
+        LOG_LEVEL_WARN => LogLevel::Warning,
+        LOG_LEVEL_ERROR => LogLevel::Error,
+        LOG_LEVEL_FATAL => LogLevel::Fatal,
+        x => LogLevel::Other(x),
+    };
+
+    // let tag = CStr::from_ptr(buf[1..].as_ptr() as *const c_char).unwrap();
+    let tag_start = 1;
+    let tag_end = buf[tag_start..]
+        .iter()
+        .copied()
+        .enumerate()
+        .map(|(i, c)| (i + tag_start, c))
+        .find(|(_, x)| *x == 0)
+        .map(|(i, _)| i)?;
+
+    let tag = String::from_utf8_lossy(&buf[tag_start..tag_end]).into();
+
+    let message_start = tag_end + 1;
+    let message_end = buf[message_start..]
+        .iter()
+        .copied()
+        .enumerate()
+        .map(|(i, c)| (i + message_start, c))
+        .find(|(_, x)| *x == 0)
+        .map(|(i, _)| i)
+        .unwrap_or(buf.len() - 1) // if the last character is not null, then `adb logcat` treats it as NULL
+        .max(message_start);
+
+    let message = String::from_utf8_lossy(&buf[message_start..message_end])
+        .trim_end_matches(|c: char| !c.is_ascii())
+        .into();
+
+    Some((
+        TextLogBuffer {
+            level,
+            tag,
+            message,
+        },
+        &buf[message_start..message_end],
+    ))
+}
+
 impl tokio_util::codec::Decoder for LogcatBinaryDecoder {
     type Item = LogMessage;
 
@@ -522,7 +948,8 @@ impl tokio_util::codec::Decoder for LogcatBinaryDecoder {
         }
 
         if src.len() < LOGGER_ENTRY_PID_OFF {
-            src.reserve(1024);
+            // room for the largest entry, so it never takes more than one allocation
+            src.reserve(LOGGER_ENTRY_MAX_SIZE);
             return Ok(None);
         }
 
@@ -572,74 +999,77 @@ impl tokio_util::codec::Decoder for LogcatBinaryDecoder {
         let lid = read_u32(src, hdr_size, LOGGER_ENTRY_LID_OFF);
         let uid = read_u32(src, hdr_size, LOGGER_ENTRY_UID_OFF);
 
-        let is_binary = if let Some(lid) = lid {
-            match lid {
-                LOG_ID_EVENTS | LOG_ID_STATS | LOG_ID_SECURITY => true,
-                _ => false,
+        let timestamp = NaiveDateTime::from_timestamp_opt(sec as i64, nsec).unwrap();
+
+        // `adb logcat` prints "beginning of <buffer>" before the first entry from each buffer,
+        // and with -D "switch to <buffer>" whenever the buffer changes after that. Emit the same,
+        // leaving the entry in `src` to be decoded on the next call. Timestamps say nothing about
+        // clears, since they're set by whoever logged the entry and can go backwards at any time,
+        // so the marker is only ever emitted once per buffer.
+        if let Some(lid) = lid {
+            let first = self.seen_log_ids.insert(lid);
+            let switched = self.last_log_id.replace(lid) != Some(lid);
+            if first || (self.dividers && switched) {
+                let name = log_id_name(lid).map_or_else(|| format!("log id {lid}"), str::to_owned);
+                let text = if first {
+                    format!("beginning of {name}")
+                } else {
+                    format!("switch to {name}")
+                };
+                return Ok(Some(LogMessage {
+                    timestamp,
+                    uid: None,
+                    pid: 0,
+                    tid: 0,
+                    lid: Some(lid),
+                    buffer: LogBuffer::Marker(text),
+                    raw: None,
+                }));
             }
-        } else {
-            false
-        };
+        }
+
+        let binary_log_id = lid.and_then(BinaryLogId::from_log_id);
 
         let buf = &src[hdr_size..][..len];
 
-        let buffer = if is_binary {
-            let tag = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
-            LogBuffer::BinaryLog(BinaryLogBuffer { tag })
+        let (buffer, raw) = if let Some(log_id) = binary_log_id {
+            match buf.get(..4) {
+                Some(tag) => (
+                    LogBuffer::BinaryLog(BinaryLogBuffer {
+                        log_id,
+                        tag: i32::from_le_bytes([tag[0], tag[1], tag[2], tag[3]]),
+                    }),
+                    &buf[4..],
+                ),
+                None => (LogBuffer::Unknown(buf.to_vec()), buf),
+            }
         } else {
-            let level = match buf[0] {
-                LOG_LEVEL_VERBOSE => LogLevel::Verbose,
-                LOG_LEVEL_DEBUG => LogLevel::Debug,
-                LOG_LEVEL_INFO => LogLevel::Info,
-                LOG_LEVEL_WARN => LogLevel::Warning,
-                LOG_LEVEL_ERROR => LogLevel::Error,
-                LOG_LEVEL_FATAL => LogLevel::Fatal,
-                x => LogLevel::Other(x),
-            };
-
-            // let tag = CStr::from_ptr(buf[1..].as_ptr() as *const c_char).unwrap();
-            let tag_start = 1;
-            let tag_end = buf[tag_start..]
-                .iter()
-                .copied()
-                .enumerate()
-                .map(|(i, c)| (i + tag_start, c))
-                .find(|(_, x)| *x == 0)
-                .map(|(i, _)| i)
-                .unwrap();
-
-            let tag = String::from_utf8_lossy(&buf[tag_start..tag_end]).into();
-
-            let message_start = tag_end + 1;
-            let message_end = buf[message_start..]
-                .iter()
-                .copied()
-                .enumerate()
-                .map(|(i, c)| (i + message_start, c))
-                .find(|(_, x)| *x == 0)
-                .map(|(i, _)| i)
-                .unwrap_or(buf.len() - 1); // if the last character is not null, then `adb logcat` treats it as NULL
-
-            let message = String::from_utf8_lossy(&buf[message_start..message_end])
-                .trim_end_matches(|c: char| !c.is_ascii())
-                .into();
-
-            LogBuffer::TextLog(TextLogBuffer {
-                level,
-                tag,
-                message,
-            })
+            match decode_text_log(buf) {
+                Some((mut buffer, raw)) => {
+                    if self.strip_ansi
+                        && buffer
+                            .message
+                            .contains(|c: char| c.is_control() && c != '\n' && c != '\t')
+                    {
+                        buffer.message = strip_control(&buffer.message);
+                    }
+                    (LogBuffer::TextLog(buffer), raw)
+                }
+                None => (LogBuffer::Unknown(buf.to_vec()), buf),
+            }
         };
+        let raw = self.keep_raw.then(|| raw.to_vec());
 
         src.advance(hdr_size + len);
 
         Ok(Some(LogMessage {
-            timestamp: NaiveDateTime::from_timestamp_opt(sec as i64, nsec).unwrap(),
+            timestamp,
             uid,
             pid,
             tid,
             lid,
             buffer,
+            raw,
         }))
     }
 }
@@ -1,26 +1,47 @@
-use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::Command,
 };
 
-use crate::devices::AdbDevice;
+use crate::{
+    commands::adb::{is_dry_run, print_command, spawn_output},
+    devices::AdbDevice,
+};
 
 fn get_fastboot() -> Command {
     tokio::process::Command::new("fastboot")
 }
 
+// set once fastboot turns out not to be installed, so it isn't looked for on every poll
+static NOT_INSTALLED: AtomicBool = AtomicBool::new(false);
+
 pub async fn devices() -> Vec<Result<AdbDevice, crate::devices::Error>> {
-    let adb = get_fastboot()
-        .args(shell_words::split("devices -l").unwrap().as_slice())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    let stdout = BufReader::new(adb.stdout.unwrap());
+    let args = shell_words::split("devices -l").unwrap();
+    if is_dry_run() {
+        print_command("fastboot", &args);
+        return Vec::new();
+    }
+    if NOT_INSTALLED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+
+    let stdout = match spawn_output(get_fastboot().args(args.as_slice())) {
+        Ok(stdout) => stdout,
+        // fastboot is optional - treat it as having no devices if it can't be run
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!("fastboot isn't installed, skipping fastboot devices from now on");
+            NOT_INSTALLED.store(true, Ordering::Relaxed);
+            return Vec::new();
+        }
+        Err(err) => {
+            tracing::debug!("failed to run fastboot: {err:?}");
+            return Vec::new();
+        }
+    };
+
+    let stdout = BufReader::new(stdout);
     let mut lines = stdout.lines();
 
     let mut devices = Vec::new();
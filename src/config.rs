@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use quick_error::quick_error;
+use serde::Deserialize;
+
+use crate::{
+    cache::{xadb_dir, CacheKey},
+    cli::{Args, Command, LogcatArgs},
+    commands::adb::LogLevel,
+    device_select::DeviceColumn,
+    theme::StyleConfig,
+    widgets::log::{ColorBy, TimeFormat},
+};
+
+fn config_location() -> PathBuf {
+    xadb_dir().join("config.toml")
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Parse(err: toml::de::Error) {
+            from()
+            display("invalid {}: {}", config_location().display(), err)
+        }
+        Io(err: std::io::Error) {
+            from()
+        }
+    }
+}
+
+/// Defaults read from `$XADB_DIR/config.toml`. Environment variables and command line flags take
+/// precedence over anything set here, so `--compact=false` undoes `compact = true`.
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub no_default: Option<bool>,
+    pub no_cache: Option<bool>,
+    pub cache_key: Option<CacheKey>,
+    pub columns: Option<Vec<DeviceColumn>>,
+    pub battery_interval: Option<u64>,
+    /// Run through `sh -c` by `xadb watch` whenever a device becomes usable over adb, with
+    /// `{serial}` replaced by its serial
+    pub on_connect: Option<String>,
+    pub logcat: LogcatConfig,
+    pub theme: ThemeConfig,
+}
+
+/// The `[logcat]` table, mirroring the flags of `xadb logcat`
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LogcatConfig {
+    pub text_only: Option<bool>,
+    pub min_level: Option<LogLevel>,
+    pub jump_level: Option<LogLevel>,
+    pub color_by: Option<ColorBy>,
+    pub time_format: Option<TimeFormat>,
+    pub compact: Option<bool>,
+    pub multiline: Option<bool>,
+    pub dividers: Option<bool>,
+    pub keep_ansi: Option<bool>,
+    pub show_pid: Option<bool>,
+    pub show_tid: Option<bool>,
+    pub line_numbers: Option<bool>,
+    pub editor_url: Option<String>,
+    pub read_buffer_size: Option<usize>,
+}
+
+/// The `[theme]` table, for terminals where the default colors are hard to read
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub selection: Option<StyleConfig>,
+}
+
+impl Config {
+    pub async fn load() -> Result<Config, Error> {
+        match tokio::fs::read_to_string(config_location()).await {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Fills in anything not already given on the command line or through the environment
+    pub fn apply(&self, args: &mut Args) {
+        args.no_default = args.no_default.or(self.no_default);
+        args.no_cache = args.no_cache.or(self.no_cache);
+        args.cache_key = args.cache_key.or(self.cache_key);
+        args.battery_interval = args.battery_interval.or(self.battery_interval);
+        if let (true, Some(columns)) = (args.columns.is_empty(), &self.columns) {
+            args.columns = columns.clone();
+        }
+
+        if let Command::Logcat(logcat) = &mut args.command {
+            self.apply_logcat(logcat);
+        }
+    }
+
+    /// Fills in defaults from the `[logcat]` table, for logs opened other than by `xadb logcat`
+    pub fn apply_logcat(&self, logcat: &mut LogcatArgs) {
+        logcat.text_only = logcat.text_only.or(self.logcat.text_only);
+        logcat.min_level = logcat.min_level.or(self.logcat.min_level);
+        logcat.jump_level = logcat.jump_level.or(self.logcat.jump_level);
+        logcat.color_by = logcat.color_by.or(self.logcat.color_by);
+        logcat.time_format = logcat.time_format.or(self.logcat.time_format);
+        logcat.compact = logcat.compact.or(self.logcat.compact);
+        logcat.multiline = logcat.multiline.or(self.logcat.multiline);
+        logcat.dividers = logcat.dividers.or(self.logcat.dividers);
+        logcat.keep_ansi = logcat.keep_ansi.or(self.logcat.keep_ansi);
+        logcat.show_pid = logcat.show_pid.or(self.logcat.show_pid);
+        logcat.show_tid = logcat.show_tid.or(self.logcat.show_tid);
+        logcat.line_numbers = logcat.line_numbers.or(self.logcat.line_numbers);
+        logcat.read_buffer_size = logcat.read_buffer_size.or(self.logcat.read_buffer_size);
+        if logcat.editor_url.is_none() {
+            logcat.editor_url = self.logcat.editor_url.clone();
+        }
+    }
+}
@@ -1,26 +1,39 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     time::{Duration, Instant},
 };
 
-use crossterm::event::{self, KeyCode};
+use crossterm::event::{KeyCode, KeyEvent};
 use quick_error::quick_error;
-use tokio::pin;
+use tokio::{
+    pin,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
 use tokio_stream::StreamExt;
 use tui::{
     backend::Backend,
-    layout::{Constraint, Layout},
+    layout::{Alignment, Constraint, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, ListState},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
+use unicode_width::UnicodeWidthStr;
 
 type CrosstermEvent = crossterm::event::Event;
 
 use crate::{
-    cache::Cache,
-    devices::{query_devices_continuously, AdbDevice, AdbDeviceProperties},
+    battery::{battery, battery_ticks},
+    cache::{self, Cache},
+    commands::adb,
+    devices::{
+        self, debounce_devices, online_devices, query_devices_continuously, AdbDevice,
+        AdbDeviceLiveProperties, AdbDeviceProperties, TransportKind,
+    },
+    emulator::emulator_name,
+    events::crossterm_event_stream,
+    theme,
+    widgets::shell::{poll_overlay, ShellOverlay, ShellOverlayState},
 };
 
 quick_error! {
@@ -51,30 +64,23 @@ impl<T> StatefulList<T> {
         }
     }
 
+    // the selection can point past the end after items are removed, so both of these treat an
+    // out of range index like the end of the list rather than indexing with it
     fn next(&mut self) {
         let i = match self.state.selected() {
-            Some(i) => Some(if i >= self.items.len() - 1 { 0 } else { i + 1 }),
-            None => {
-                if self.items.is_empty() {
-                    None
-                } else {
-                    Some(0)
-                }
-            }
+            _ if self.items.is_empty() => None,
+            Some(i) if i + 1 < self.items.len() => Some(i + 1),
+            _ => Some(0),
         };
         self.state.select(i);
     }
 
     fn previous(&mut self) {
         let i = match self.state.selected() {
-            Some(i) => Some(if i == 0 { self.items.len() - 1 } else { i - 1 }),
-            None => {
-                if self.items.is_empty() {
-                    None
-                } else {
-                    Some(0)
-                }
-            }
+            _ if self.items.is_empty() => None,
+            None => Some(0),
+            Some(i) if i == 0 || i >= self.items.len() => Some(self.items.len() - 1),
+            Some(i) => Some(i - 1),
         };
         self.state.select(i);
     }
@@ -84,29 +90,222 @@ impl<T> StatefulList<T> {
     }
 
     fn delete_selected(&mut self) {
-        if let Some(index) = self.state.selected() {
-            self.items.remove(index);
+        let Some(index) = self.state.selected() else {
+            return;
+        };
+        if index >= self.items.len() {
+            self.state.select(None);
+            return;
+        }
 
-            // if no items left, then deselect
-            if self.items.is_empty() {
-                self.state.select(None);
-            } else {
-                // move to next
-                self.next();
-            }
+        self.items.remove(index);
+
+        // the following item has moved into `index`, so keep it selected. if no items are left,
+        // then deselect
+        if self.items.is_empty() {
+            self.state.select(None);
+        } else {
+            self.state.select(Some(index.min(self.items.len() - 1)));
         }
     }
 
+    /// Stable sorts the items, keeping the same item selected
+    fn sort_by_key<K: Ord>(&mut self, mut key: impl FnMut(&T) -> K) {
+        let mut order: Vec<usize> = (0..self.items.len()).collect();
+        order.sort_by_key(|i| key(&self.items[*i]));
+
+        let selected = self
+            .state
+            .selected()
+            .and_then(|selected| order.iter().position(|i| *i == selected));
+
+        let mut items: Vec<Option<T>> = self.items.drain(..).map(Some).collect();
+        self.items = order.iter().map(|i| items[*i].take().unwrap()).collect();
+        self.state.select(selected);
+    }
+
     fn selected(&self) -> Option<&T> {
         self.items.get(self.state.selected()?)
     }
 }
 
+/// A device property that can be shown in the selector
+#[derive(clap::ValueEnum, serde::Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceColumn {
+    /// The name adb knows the device by, which is the address for network devices
+    Serial,
+    /// The device's own serial number, which for network devices differs from `serial`
+    Serialno,
+    Product,
+    Model,
+    Device,
+    TransportId,
+    Battery,
+    /// Android version and API level
+    Version,
+    /// Whether the device is online and in which state. Shown beside the first column.
+    State,
+}
+
+impl DeviceColumn {
+    fn label(&self) -> &'static str {
+        match self {
+            DeviceColumn::Serial => "serial",
+            DeviceColumn::Serialno => "serialno",
+            DeviceColumn::Product => "product",
+            DeviceColumn::Model => "model",
+            DeviceColumn::Device => "device",
+            DeviceColumn::TransportId => "transport_id",
+            DeviceColumn::Battery => "battery",
+            DeviceColumn::Version => "version",
+            DeviceColumn::State => "state",
+        }
+    }
+}
+
+/// How the selector is set up
+#[derive(Clone)]
+pub struct DeviceSelectOptions {
+    /// Keep the cache in memory instead of reading and writing it on disk
+    pub no_cache: bool,
+    /// The first column heads each row, the rest are listed beneath it
+    pub columns: Vec<DeviceColumn>,
+}
+
+impl Default for DeviceSelectOptions {
+    fn default() -> Self {
+        Self {
+            no_cache: false,
+            columns: vec![
+                DeviceColumn::Serial,
+                DeviceColumn::State,
+                DeviceColumn::Product,
+                DeviceColumn::Version,
+            ],
+        }
+    }
+}
+
 #[derive(Debug)]
 struct DeviceItem {
     serial: String,
     live: Option<AdbDeviceProperties>,
     cache: Option<AdbDeviceProperties>,
+    battery: Option<i32>,
+    // AVD name, for emulators
+    emulator_name: Option<String>,
+}
+
+impl DeviceItem {
+    fn is_favorite(&self) -> bool {
+        self.cache.as_ref().is_some_and(|cache| cache.favorite)
+    }
+
+    // favorites first, then emulators after physical devices
+    fn sort_key(&self) -> (bool, TransportKind) {
+        (
+            !self.is_favorite(),
+            TransportKind::from_serial(&self.serial),
+        )
+    }
+
+    // prefers the live value, falling back to the last one seen
+    fn property(&self, get: impl Fn(&AdbDeviceLiveProperties) -> Option<String>) -> Option<String> {
+        let live = |properties: &Option<AdbDeviceProperties>| {
+            properties.as_ref()?.live.as_ref().and_then(&get)
+        };
+        live(&self.live).or_else(|| live(&self.cache).map(|value| format!("{value} (stale)")))
+    }
+
+    fn column(&self, column: DeviceColumn) -> String {
+        let value = match column {
+            DeviceColumn::Serial => Some(self.serial.clone()),
+            DeviceColumn::Serialno => self.property(|l| l.serialno.clone()),
+            // unknown products show the serial so the row still identifies the device
+            DeviceColumn::Product => Some(
+                self.property(|l| Some(l.product.clone()))
+                    .unwrap_or(self.serial.clone()),
+            ),
+            DeviceColumn::Model => self.property(|l| Some(l.model.clone())),
+            DeviceColumn::Device => self.property(|l| Some(l.device.clone())),
+            DeviceColumn::TransportId => self.property(|l| Some(l.transport_id.to_string())),
+            DeviceColumn::Version => self.property(AdbDeviceLiveProperties::version),
+            DeviceColumn::Battery => self.battery.map(|level| format!("{level}%")),
+            DeviceColumn::State => None,
+        };
+        value.unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// Which devices the selector lists, by connection state
+#[derive(Copy, Clone, PartialEq, Default)]
+enum StateFilter {
+    #[default]
+    All,
+    /// Booted and reachable over adb
+    Online,
+    Fastboot,
+    /// In recovery or waiting for a sideload
+    Recovery,
+    /// Disconnected, or connected but not responding
+    Offline,
+}
+
+impl StateFilter {
+    fn next(self) -> StateFilter {
+        match self {
+            StateFilter::All => StateFilter::Online,
+            StateFilter::Online => StateFilter::Fastboot,
+            StateFilter::Fastboot => StateFilter::Recovery,
+            StateFilter::Recovery => StateFilter::Offline,
+            StateFilter::Offline => StateFilter::All,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            StateFilter::All => "all",
+            StateFilter::Online => "online",
+            StateFilter::Fastboot => "fastboot",
+            StateFilter::Recovery => "recovery",
+            StateFilter::Offline => "offline",
+        }
+    }
+
+    fn matches(self, item: &DeviceItem) -> bool {
+        let state = item
+            .live
+            .as_ref()
+            .map(|live| live.connection_state.as_str());
+        match self {
+            StateFilter::All => true,
+            StateFilter::Online => state == Some("device"),
+            StateFilter::Fastboot => state == Some("fastboot"),
+            StateFilter::Recovery => matches!(state, Some("recovery" | "sideload")),
+            StateFilter::Offline => matches!(state, None | Some("offline")),
+        }
+    }
+}
+
+// what a lookup or action running in the background found out about a device
+enum Lookup {
+    // `ro.build.version.release`, `ro.build.version.sdk` and `ro.serialno`, if they could be read
+    Props(String, Option<Vec<Option<String>>>),
+    EmulatorName(String, String),
+    Battery(String, Option<i32>),
+    // how pressing a button went, for a toast
+    KeyEvent(String),
+}
+
+/// How the selector was left
+pub enum SelectOutcome {
+    /// The device to use from the shell, picked with Enter
+    Selected(String),
+    /// The device whose log to show straight away, picked with l
+    ViewLogs(String),
+    /// Quit without picking a device
+    Cancelled,
 }
 
 /// This struct holds the current state of the app. In particular, it has the `items` field which is a wrapper
@@ -117,49 +316,74 @@ struct DeviceItem {
 /// Check the drawing logic for items on how to specify the highlighting style for selected items.
 pub struct DeviceSelectApp {
     items: StatefulList<DeviceItem>,
+    columns: Vec<DeviceColumn>,
     cache: Cache,
+    shell: Option<ShellOverlayState>,
+    // commands run in the shell overlay, kept while it's closed
+    shell_history: VecDeque<String>,
+    state_filter: StateFilter,
+    // a short confirmation shown at the bottom until it expires
+    toast: Option<(String, Instant)>,
+    // whether `cache` has changes that haven't been written out yet
+    cache_dirty: bool,
+    // when the list was opened, to animate the spinner while waiting for devices
+    opened: Instant,
+    // lookups are run in the background so a slow device can't hold up the list, and send back
+    // what they find here
+    lookups: UnboundedSender<Lookup>,
+    lookup_results: UnboundedReceiver<Lookup>,
+    // devices whose props or battery are being looked up, so they're only asked once at a time
+    pending_props: HashSet<String>,
+    pending_battery: HashSet<String>,
+    // emulators whose console has been asked for the AVD name. it's only tried once, since a
+    // console that didn't answer isn't going to start
+    emulator_names_asked: HashSet<String>,
 }
 
+const TOAST_DURATION: Duration = Duration::from_secs(2);
+
+// device updates arrive every second, but are only written to disk this often
+const CACHE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
 impl DeviceSelectApp {
-    pub async fn load_initial_state() -> Result<DeviceSelectApp, Error> {
-        let cache = Cache::load_from_disk();
-
-        let live_devices = crate::devices::online_devices();
-
-        let (cache, live_devices) = tokio::join!(cache, live_devices);
-        let mut cache = cache?;
-
-        let mut live_device_map = HashMap::new();
-
-        let mut devices = Vec::new();
-        for (i, device) in live_devices.into_iter().enumerate() {
-            if let Ok(device) = device {
-                cache.save_device(&device.connection_name, &device.properties);
-                live_device_map.insert(device.connection_name.clone(), i);
-                devices.push(DeviceItem {
-                    serial: device.connection_name,
-                    live: Some(device.properties),
-                    cache: None,
-                });
-            }
-        }
+    /// Loads the cached devices. Live device state is filled in once `run` starts polling, so
+    /// this never waits on adb or fastboot. With `no_cache`, only live devices are listed.
+    pub async fn load_initial_state(
+        options: &DeviceSelectOptions,
+    ) -> Result<DeviceSelectApp, Error> {
+        let cache = Cache::load(options.no_cache).await?;
 
-        cache.persist().await?;
+        let devices = cache
+            .devices
+            .iter()
+            .map(|(serial, properties)| DeviceItem {
+                serial: serial.clone(),
+                live: None,
+                cache: Some(properties.clone()),
+                battery: None,
+                emulator_name: None,
+            })
+            .collect();
 
-        for (serial, properties) in &cache.devices {
-            match live_device_map.get(serial) {
-                Some(index) => devices[*index].cache = Some(properties.clone()),
-                None => devices.push(DeviceItem {
-                    serial: serial.clone(),
-                    live: None,
-                    cache: Some(properties.clone()),
-                }),
-            }
-        }
+        let mut items = StatefulList::with_items(devices);
+        items.sort_by_key(DeviceItem::sort_key);
 
+        let (lookups, lookup_results) = unbounded_channel();
         Ok(DeviceSelectApp {
-            items: StatefulList::with_items(devices),
+            items,
+            columns: options.columns.clone(),
             cache,
+            shell: None,
+            shell_history: VecDeque::new(),
+            state_filter: StateFilter::All,
+            toast: None,
+            cache_dirty: false,
+            opened: Instant::now(),
+            lookups,
+            lookup_results,
+            pending_props: HashSet::new(),
+            pending_battery: HashSet::new(),
+            emulator_names_asked: HashSet::new(),
         })
     }
 
@@ -171,10 +395,14 @@ impl DeviceSelectApp {
 
         // check which devices have new state
         for current in &mut self.items.items {
-            if let Some(new_device) = new_devices.remove(&current.serial) {
+            if let Some(mut new_device) = new_devices.remove(&current.serial) {
+                let cache = current.cache.as_mut().unwrap();
+                if let (Some(live), Some(previous)) = (&mut new_device.properties.live, &cache.live)
+                {
+                    live.carry_over_lookups(previous);
+                }
                 current.live = Some(new_device.properties.clone());
 
-                let cache = current.cache.as_mut().unwrap();
                 cache.connection_state = new_device.properties.connection_state;
                 cache.devpath = new_device.properties.devpath;
                 if let Some(live) = new_device.properties.live {
@@ -193,46 +421,346 @@ impl DeviceSelectApp {
                 serial,
                 live: Some(device.properties.clone()),
                 cache: Some(device.properties),
+                battery: None,
+                emulator_name: None,
             });
         }
 
-        self.cache.persist().await?;
+        self.items.sort_by_key(DeviceItem::sort_key);
+        self.update_emulator_names();
+
+        // the rest are refreshed on the battery interval
+        if self.columns.contains(&DeviceColumn::Battery) {
+            self.update_battery(true);
+        }
+        if self.columns.contains(&DeviceColumn::Version)
+            || self.columns.contains(&DeviceColumn::Serialno)
+            || cache::keyed_by_serialno()
+        {
+            self.update_props();
+        }
+
+        self.cache_dirty = true;
 
         Ok(())
     }
 
+    async fn flush_cache(&mut self) -> Result<(), Error> {
+        if self.cache_dirty {
+            self.cache.persist().await?;
+            self.cache_dirty = false;
+        }
+        Ok(())
+    }
+
+    // asks the console of each running emulator for its AVD name, once
+    fn update_emulator_names(&mut self) {
+        for item in &self.items.items {
+            if item.live.is_none()
+                || TransportKind::from_serial(&item.serial) != TransportKind::Emulator
+                || !self.emulator_names_asked.insert(item.serial.clone())
+            {
+                continue;
+            }
+
+            let serial = item.serial.clone();
+            let lookups = self.lookups.clone();
+            tokio::spawn(async move {
+                if let Some(name) = emulator_name(&serial).await {
+                    let _ = lookups.send(Lookup::EmulatorName(serial, name));
+                }
+            });
+        }
+    }
+
+    // looks up the Android version and serial number of booted devices that don't have them yet.
+    // they don't change without a reboot, so they're kept in the cache from then on
+    fn update_props(&mut self) {
+        for item in &self.items.items {
+            let Some(AdbDeviceProperties {
+                connection_state,
+                live: Some(live),
+                ..
+            }) = &item.live
+            else {
+                continue;
+            };
+            if connection_state != "device"
+                || (live.version().is_some() && live.serialno.is_some())
+                || !self.pending_props.insert(item.serial.clone())
+            {
+                continue;
+            }
+
+            let serial = item.serial.clone();
+            let lookups = self.lookups.clone();
+            tokio::spawn(async move {
+                let props = [
+                    "ro.build.version.release",
+                    "ro.build.version.sdk",
+                    "ro.serialno",
+                ];
+                let props = adb::getprop(adb::transport(), &serial, &props).await.ok();
+                // the list may have closed in the meantime
+                let _ = lookups.send(Lookup::Props(serial, props));
+            });
+        }
+    }
+
+    fn apply_lookup(&mut self, lookup: Lookup) {
+        match lookup {
+            Lookup::Props(serial, props) => {
+                self.pending_props.remove(&serial);
+                let Some(props) = props else {
+                    return;
+                };
+                let Some(item) = self.item_mut(&serial) else {
+                    return;
+                };
+
+                let sdk = props[1].as_ref().and_then(|sdk| sdk.parse().ok());
+                for properties in [&mut item.live, &mut item.cache].into_iter().flatten() {
+                    if let Some(live) = &mut properties.live {
+                        live.android_version = props[0].clone();
+                        live.sdk = sdk;
+                        live.serialno = props[2].clone();
+                    }
+                }
+                if let Some(live) = item.live.clone() {
+                    self.cache.save_device(&serial, &live);
+                    self.cache_dirty = true;
+                }
+            }
+            Lookup::EmulatorName(serial, name) => {
+                if let Some(item) = self.item_mut(&serial) {
+                    item.emulator_name = Some(name);
+                }
+            }
+            Lookup::Battery(serial, level) => {
+                self.pending_battery.remove(&serial);
+                if let Some(item) = self.item_mut(&serial) {
+                    item.battery = level;
+                }
+            }
+            Lookup::KeyEvent(message) => self.toast = Some((message, Instant::now())),
+        }
+    }
+
+    fn item_mut(&mut self, serial: &str) -> Option<&mut DeviceItem> {
+        self.items
+            .items
+            .iter_mut()
+            .find(|item| item.serial == serial)
+    }
+
+    // only devices that are booted can report their battery. `only_missing` skips devices whose
+    // level is already known
+    fn update_battery(&mut self, only_missing: bool) {
+        for item in &mut self.items.items {
+            if item
+                .live
+                .as_ref()
+                .is_none_or(|live| live.connection_state != "device")
+            {
+                item.battery = None;
+                continue;
+            }
+            if (only_missing && item.battery.is_some())
+                || !self.pending_battery.insert(item.serial.clone())
+            {
+                continue;
+            }
+
+            let serial = item.serial.clone();
+            let lookups = self.lookups.clone();
+            tokio::spawn(async move {
+                let level = battery(adb::transport(), Some(&serial)).await.ok();
+                let _ = lookups.send(Lookup::Battery(serial, level));
+            });
+        }
+    }
+
+    // the highlighted device, unless the filter hides it
+    fn selected(&self) -> Option<&DeviceItem> {
+        self.items
+            .selected()
+            .filter(|item| self.state_filter.matches(item))
+    }
+
+    // moves the selection to the next device the filter shows, or deselects if there are none
+    fn select_visible(&mut self, forward: bool) {
+        for _ in 0..self.items.items.len() {
+            if forward {
+                self.items.next();
+            } else {
+                self.items.previous();
+            }
+            if self.selected().is_some() {
+                return;
+            }
+        }
+        self.items.unselect();
+    }
+
+    // moves the selection off a device the filter hides
+    fn reselect_if_hidden(&mut self) {
+        if self.items.state.selected().is_some() && self.selected().is_none() {
+            self.select_visible(true);
+        }
+    }
+
+    fn cycle_state_filter(&mut self) {
+        self.state_filter = self.state_filter.next();
+        self.reselect_if_hidden();
+    }
+
+    // remembers the highlighted device as the default, returning its serial
+    async fn pick(&mut self) -> Result<Option<String>, Error> {
+        let Some(serial) = self.selected().map(|item| item.serial.clone()) else {
+            return Ok(None);
+        };
+        self.cache.last_selected = Some(serial.clone());
+        self.cache_dirty = true;
+        Ok(Some(serial))
+    }
+
+    // pins the highlighted device to the top, or unpins it
+    fn toggle_favorite(&mut self) {
+        let Some(serial) = self.selected().map(|item| item.serial.clone()) else {
+            return;
+        };
+
+        let favorite = self.cache.toggle_favorite(&serial);
+        if let Some(item) = self
+            .items
+            .items
+            .iter_mut()
+            .find(|item| item.serial == serial)
+        {
+            if let Some(cache) = &mut item.cache {
+                cache.favorite = favorite;
+            }
+        }
+        self.items.sort_by_key(DeviceItem::sort_key);
+        self.cache_dirty = true;
+
+        let message = if favorite {
+            format!("pinned {serial}")
+        } else {
+            format!("unpinned {serial}")
+        };
+        self.toast = Some((message, Instant::now()));
+    }
+
+    // explains in a toast why the highlighted device can't do what needs Android running, if it
+    // isn't booted
+    fn check_booted(&mut self) -> bool {
+        let Some(item) = self.selected() else {
+            return true;
+        };
+        let Some(live) = &item.live else {
+            return true;
+        };
+        let Some(hint) = devices::not_booted_hint(&live.connection_state) else {
+            return true;
+        };
+
+        let message = format!("{} is in {}; {hint}", item.serial, live.connection_state);
+        self.toast = Some((message, Instant::now()));
+        false
+    }
+
+    // presses a button on the highlighted device, confirming with a toast once adb is done
+    fn send_keyevent(&mut self, keycode: &'static str, action: &'static str) {
+        if !self.check_booted() {
+            return;
+        }
+        let Some(serial) = self.selected().map(|item| item.serial.clone()) else {
+            return;
+        };
+
+        let lookups = self.lookups.clone();
+        tokio::spawn(async move {
+            let message = match adb::send_keyevent(adb::transport(), &serial, keycode).await {
+                Ok(()) => format!("{action} {serial}"),
+                Err(err) => format!("{keycode} failed: {err}"),
+            };
+            let _ = lookups.send(Lookup::KeyEvent(message));
+        });
+    }
+
     pub async fn run<B: Backend>(
         &mut self,
         terminal: &mut Terminal<B>,
         tick_rate: Duration,
-    ) -> Result<Option<String>, Error> {
-        let mut last_tick = Instant::now();
-        let query_devices = query_devices_continuously(Duration::from_secs(1));
+    ) -> Result<SelectOutcome, Error> {
+        let outcome = self.run_until_done(terminal, tick_rate).await;
+        // write out whatever changed since the last flush, even when quitting on an error
+        let flushed = self.flush_cache().await;
+        // the device queries and any shell commands are abandoned along with the list
+        adb::kill_children();
+        let outcome = outcome?;
+        flushed?;
+        Ok(outcome)
+    }
+
+    async fn run_until_done<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        tick_rate: Duration,
+    ) -> Result<SelectOutcome, Error> {
+        let query_devices = debounce_devices(
+            query_devices_continuously(Duration::from_secs(1)),
+            Duration::from_millis(200),
+        );
         pin!(query_devices);
 
+        let key_events = crossterm_event_stream().filter_map(|event| match event {
+            Ok(CrosstermEvent::Key(key)) => Some(Ok(key)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        });
+        pin!(key_events);
+
+        let mut tick = tokio::time::interval(tick_rate);
+        let mut flush = tokio::time::interval(CACHE_FLUSH_INTERVAL);
+        let mut battery_ticks = battery_ticks();
+        let show_battery = self.columns.contains(&DeviceColumn::Battery);
+
         loop {
             terminal.draw(|f| self.ui(f))?;
 
-            let timeout = tick_rate
-                .checked_sub(last_tick.elapsed())
-                .unwrap_or_else(|| Duration::from_secs(0));
-
             enum Event {
                 Devices(Vec<AdbDevice>),
-                CrosstermEvent(Option<CrosstermEvent>),
+                Key(KeyEvent),
+                Lookup(Lookup),
+                Tick,
             }
 
             let next = tokio::select! {
                 devices = query_devices.next() => {
                     Event::Devices(devices.unwrap())
                 },
-                is_event = tokio::task::spawn_blocking(move || crossterm::event::poll(timeout)) => {
-                    let is_event = is_event.unwrap();
-                    if is_event? {
-                        Event::CrosstermEvent(Some(event::read()?))
-                    } else {
-                        Event::CrosstermEvent(None)
-                    }
+                key = key_events.next() => {
+                    Event::Key(key.unwrap()?)
+                },
+                // never ends, since `self.lookups` is a sender
+                lookup = self.lookup_results.recv() => {
+                    Event::Lookup(lookup.unwrap())
+                },
+                _ = poll_overlay(&mut self.shell) => {
+                    Event::Tick
+                },
+                _ = tick.tick() => {
+                    Event::Tick
+                },
+                _ = flush.tick() => {
+                    self.flush_cache().await?;
+                    Event::Tick
+                },
+                _ = battery_ticks.tick(), if show_battery => {
+                    self.update_battery(false);
+                    Event::Tick
                 },
             };
 
@@ -240,34 +768,71 @@ impl DeviceSelectApp {
                 Event::Devices(devices) => {
                     self.update_devices(devices).await?;
                 }
-                Event::CrosstermEvent(event) => {
-                    match event {
-                        Some(CrosstermEvent::Key(key)) => match key.code {
-                            KeyCode::Char('q') => return Ok(None),
-                            KeyCode::Left | KeyCode::Char('h') => self.items.unselect(),
-                            KeyCode::Down | KeyCode::Char('j') => self.items.next(),
-                            KeyCode::Up | KeyCode::Char('k') => self.items.previous(),
-                            KeyCode::Delete => {
-                                if let Some(item) = self.items.selected() {
-                                    self.cache.remove_device(&item.serial);
-                                    self.cache.persist().await?;
-                                    self.items.delete_selected();
-                                }
-                            }
-                            KeyCode::Enter => {
-                                if let Some(item) = self.items.selected() {
-                                    return Ok(Some(item.serial.clone()));
-                                }
-                            }
-                            _ => {}
-                        },
-                        _ => {}
-                    }
-
-                    if last_tick.elapsed() >= tick_rate {
-                        last_tick = Instant::now();
+                Event::Lookup(lookup) => self.apply_lookup(lookup),
+                Event::Key(key) if self.shell.is_some() => {
+                    if !self.shell.as_mut().unwrap().key(key) {
+                        self.shell_history = self.shell.take().unwrap().into_history();
                     }
                 }
+                Event::Key(key) => match key.code {
+                    KeyCode::Char('q') => return Ok(SelectOutcome::Cancelled),
+                    KeyCode::Char('r') => {
+                        let devices = online_devices().await;
+                        self.update_devices(devices).await?;
+                    }
+                    KeyCode::Left | KeyCode::Char('h') => self.items.unselect(),
+                    KeyCode::Down | KeyCode::Char('j') => self.select_visible(true),
+                    KeyCode::Up | KeyCode::Char('k') => self.select_visible(false),
+                    KeyCode::Char('s') => self.cycle_state_filter(),
+                    KeyCode::Char('p') => self.send_keyevent("KEYCODE_POWER", "toggled screen of"),
+                    KeyCode::Char('w') => self.send_keyevent("KEYCODE_WAKEUP", "woke"),
+                    KeyCode::Char(':') => {
+                        // run against the highlighted device, or adb's default without one
+                        let serial = self.selected().map(|item| item.serial.clone());
+                        self.shell = Some(ShellOverlayState::new(
+                            serial,
+                            std::mem::take(&mut self.shell_history),
+                        ));
+                    }
+                    KeyCode::Char('.') => {
+                        let serial = self.selected().map(|item| item.serial.clone());
+                        let mut shell =
+                            ShellOverlayState::new(serial, std::mem::take(&mut self.shell_history));
+                        if shell.rerun_last() {
+                            self.shell = Some(shell);
+                        } else {
+                            self.shell_history = shell.into_history();
+                            self.toast =
+                                Some(("no shell command to re-run".to_string(), Instant::now()));
+                        }
+                    }
+                    KeyCode::Char('f') => self.toggle_favorite(),
+                    KeyCode::Delete => {
+                        if let Some(serial) = self.selected().map(|item| item.serial.clone()) {
+                            self.cache.remove_device(&serial);
+                            self.cache_dirty = true;
+                            self.items.delete_selected();
+                            self.reselect_if_hidden();
+                        }
+                    }
+                    KeyCode::Enter => {
+                        if let Some(serial) = self.pick().await? {
+                            return Ok(SelectOutcome::Selected(serial));
+                        }
+                    }
+                    KeyCode::Char('l') => {
+                        let serial = if self.check_booted() {
+                            self.pick().await?
+                        } else {
+                            None
+                        };
+                        if let Some(serial) = serial {
+                            return Ok(SelectOutcome::ViewLogs(serial));
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Tick => {}
             }
         }
     }
@@ -277,48 +842,95 @@ impl DeviceSelectApp {
             .constraints([Constraint::Percentage(100)])
             .split(f.size());
 
+        let title = match self.state_filter {
+            StateFilter::All => "devices".to_string(),
+            filter => format!("devices [{}]", filter.label()),
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+
+        if self.items.items.is_empty() {
+            // the list fills in by itself as soon as a device shows up
+            render_waiting(f, chunks[0], block, self.opened.elapsed());
+        } else if !self
+            .items
+            .items
+            .iter()
+            .any(|item| self.state_filter.matches(item))
+        {
+            let empty = Paragraph::new(format!(
+                "No {} devices. Press s to change the filter.",
+                self.state_filter.label()
+            ))
+            .block(block)
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true });
+            f.render_widget(empty, chunks[0]);
+        } else {
+            self.render_list(f, chunks[0], block);
+        }
+
+        if let Some((message, shown)) = &self.toast {
+            if shown.elapsed() < TOAST_DURATION {
+                render_toast(f, chunks[0], message);
+            } else {
+                self.toast = None;
+            }
+        }
+
+        if let Some(shell) = &mut self.shell {
+            f.render_stateful_widget(ShellOverlay::new(), f.size(), shell);
+        }
+
+        theme::strip_colors(f);
+    }
+
+    fn render_list<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, block: Block) {
         // Iterate through all elements in the `items` app and append some debug text to it.
         let items: Vec<ListItem> = self
             .items
             .items
             .iter()
+            .filter(|i| self.state_filter.matches(i))
             .map(|i| {
-                let product = match &i.live {
-                    Some(AdbDeviceProperties {
-                        live: Some(live), ..
-                    }) => live.product.clone(),
-                    _ => match &i.cache {
-                        Some(AdbDeviceProperties {
-                            live: Some(live), ..
-                        }) => format!("{} (stale)", live.product),
-                        _ => i.serial.clone(),
-                    },
-                };
+                let mut columns = self
+                    .columns
+                    .iter()
+                    .copied()
+                    .filter(|c| *c != DeviceColumn::State);
 
                 // build top line
-                let mut top_line: Vec<Span> = vec![i.serial.as_str().into()];
-                if let Some(live) = &i.live {
-                    let color = match live.connection_state.as_str() {
-                        "device" => Color::Green,
-                        "fastboot" => Color::Yellow,
-                        _ => Color::Cyan,
-                    };
-
+                let mut top_line: Vec<Span> = Vec::new();
+                if i.is_favorite() {
+                    top_line.push(Span::styled("★ ", Style::default().fg(Color::Yellow)));
+                }
+                if let Some(first) = columns.next() {
+                    top_line.push(Span::raw(i.column(first)));
+                }
+                if let Some(name) = &i.emulator_name {
                     top_line.push(Span::styled(
-                        format!(" (online, {})", live.connection_state),
-                        Style::default().fg(color),
+                        format!(" [{name}]"),
+                        Style::default().fg(Color::Magenta),
                     ));
-                } else {
-                    top_line.push(Span::styled(" (offline)", Style::default().fg(Color::Red)));
+                }
+                if self.columns.contains(&DeviceColumn::State) {
+                    if let Some(live) = &i.live {
+                        top_line.push(Span::styled(
+                            format!(" (online, {})", live.connection_state),
+                            Style::default()
+                                .fg(theme::connection_state_color(&live.connection_state)),
+                        ));
+                    } else {
+                        top_line.push(Span::styled(" (offline)", Style::default().fg(Color::Red)));
+                    }
                 }
 
-                let lines = vec![
-                    Spans::from(top_line),
+                let mut lines = vec![Spans::from(top_line)];
+                lines.extend(columns.map(|column| {
                     Spans::from(Span::styled(
-                        format!("product: {product}"),
+                        format!("{}: {}", column.label(), i.column(column)),
                         Style::default().add_modifier(Modifier::ITALIC),
-                    )),
-                ];
+                    ))
+                }));
 
                 ListItem::new(lines)
             })
@@ -326,15 +938,114 @@ impl DeviceSelectApp {
 
         // Create a List from all list items and highlight the currently selected one
         let items = List::new(items)
-            .block(Block::default().borders(Borders::ALL).title("devices"))
-            .highlight_style(
-                Style::default()
-                    .fg(Color::Black)
-                    .bg(Color::Gray)
-                    .add_modifier(Modifier::BOLD),
-            );
+            .block(block)
+            .highlight_style(theme::selection_style());
 
         // We can now render the item list
-        f.render_stateful_widget(items, chunks[0], &mut self.items.state);
+        if self.state_filter == StateFilter::All {
+            f.render_stateful_widget(items, area, &mut self.items.state);
+        } else {
+            // the selection indexes all items, so find where it lands among the shown ones
+            let selected = self.selected().map(|selected| {
+                self.items
+                    .items
+                    .iter()
+                    .filter(|i| self.state_filter.matches(i))
+                    .position(|i| std::ptr::eq(i, selected))
+                    .unwrap()
+            });
+            let mut state = ListState::default();
+            state.select(selected);
+            f.render_stateful_widget(items, area, &mut state);
+        }
+    }
+}
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+// matches the rate the list is redrawn at while nothing else is happening
+const SPINNER_FRAME: Duration = Duration::from_millis(250);
+
+// says the list is waiting for a device to connect, vertically centered in `area`
+fn render_waiting<B: Backend>(f: &mut Frame<B>, area: Rect, block: Block, elapsed: Duration) {
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let frame = elapsed.as_millis() / SPINNER_FRAME.as_millis();
+    let spinner = SPINNER[frame as usize % SPINNER.len()];
+    let text = format!(
+        "{spinner} Waiting for devices… (connect via USB or run `adb connect`)\n\nPress q to quit."
+    );
+    // room for the text to wrap on narrow terminals
+    let height = 6.min(inner.height);
+    let message = Rect {
+        y: inner.y + (inner.height - height) / 2,
+        height,
+        ..inner
+    };
+    f.render_widget(
+        Paragraph::new(text)
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true }),
+        message,
+    );
+}
+
+// draws `message` over the bottom border of `area`, right aligned
+fn render_toast<B: Backend>(f: &mut Frame<B>, area: Rect, message: &str) {
+    let width = (message.width() as u16 + 2).min(area.width.saturating_sub(2));
+    let toast = Rect {
+        x: area.right().saturating_sub(width + 1),
+        y: area.bottom().saturating_sub(1),
+        width,
+        height: 1.min(area.height),
+    };
+    f.render_widget(Clear, toast);
+    f.render_widget(
+        Paragraph::new(format!(" {message} "))
+            .style(Style::default().fg(Color::Black).bg(Color::Cyan)),
+        toast,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_list_never_selects() {
+        let mut list = StatefulList::<u32>::with_items(vec![]);
+        list.next();
+        assert_eq!(list.state.selected(), None);
+        list.previous();
+        assert_eq!(list.state.selected(), None);
+        list.delete_selected();
+        assert_eq!(list.state.selected(), None);
+    }
+
+    #[test]
+    fn single_item_list_wraps_to_itself() {
+        let mut list = StatefulList::with_items(vec![1]);
+        list.next();
+        assert_eq!(list.state.selected(), Some(0));
+        list.next();
+        assert_eq!(list.state.selected(), Some(0));
+        list.previous();
+        assert_eq!(list.state.selected(), Some(0));
+
+        list.delete_selected();
+        assert!(list.items.is_empty());
+        assert_eq!(list.state.selected(), None);
+    }
+
+    #[test]
+    fn stale_selection_past_the_end_wraps() {
+        let mut list = StatefulList::with_items(vec![1]);
+        list.state.select(Some(3));
+        list.previous();
+        assert_eq!(list.state.selected(), Some(0));
+
+        list.state.select(Some(3));
+        list.next();
+        assert_eq!(list.state.selected(), Some(0));
     }
 }
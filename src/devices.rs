@@ -1,4 +1,10 @@
-use std::{num::ParseIntError, str::Utf8Error, time::Duration};
+use std::{
+    collections::HashMap,
+    num::ParseIntError,
+    str::Utf8Error,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use async_stream::stream;
 use bytes::Buf;
@@ -25,6 +31,9 @@ pub struct AdbDeviceProperties {
     pub devpath: String,
     #[serde(flatten)]
     pub live: Option<AdbDeviceLiveProperties>,
+    /// Pinned to the top of the selector. Only ever set in the cache, adb knows nothing of it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub favorite: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -33,6 +42,62 @@ pub struct AdbDeviceLiveProperties {
     pub model: String,
     pub device: String,
     pub transport_id: usize,
+    /// `ro.build.version.release`, e.g. `14`. adb doesn't report this, so it's looked up separately
+    /// and carried over from the cache until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub android_version: Option<String>,
+    /// `ro.build.version.sdk`, the API level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sdk: Option<u32>,
+    /// `ro.serialno`, the device's own serial. The connection name adb lists devices by is the
+    /// same for USB devices, but for network devices it's the address instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serialno: Option<String>,
+}
+
+impl AdbDeviceLiveProperties {
+    /// Like `Android 14 (API 34)`, if the version has been looked up
+    pub fn version(&self) -> Option<String> {
+        match (&self.android_version, self.sdk) {
+            (Some(release), Some(sdk)) => Some(format!("Android {release} (API {sdk})")),
+            (Some(release), None) => Some(format!("Android {release}")),
+            (None, Some(sdk)) => Some(format!("API {sdk}")),
+            (None, None) => None,
+        }
+    }
+
+    /// Keeps the properties looked up from `previous` if this doesn't have them yet
+    pub fn carry_over_lookups(&mut self, previous: &AdbDeviceLiveProperties) {
+        if self.android_version.is_none() && self.sdk.is_none() {
+            self.android_version = previous.android_version.clone();
+            self.sdk = previous.sdk;
+        }
+        if self.serialno.is_none() {
+            self.serialno = previous.serialno.clone();
+        }
+    }
+}
+
+/// How a device is attached, as far as can be told from its serial. Ordered the way the selector
+/// groups devices.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum TransportKind {
+    Usb,
+    /// Connected with `adb connect host:port` or discovered over mdns
+    Tcp,
+    Emulator,
+}
+
+impl TransportKind {
+    pub fn from_serial(serial: &str) -> TransportKind {
+        if crate::emulator::console_port(serial).is_some() {
+            TransportKind::Emulator
+        } else if serial.contains(':') || serial.contains("._adb-tls-") {
+            TransportKind::Tcp
+        } else {
+            TransportKind::Usb
+        }
+    }
 }
 
 quick_error! {
@@ -63,65 +128,195 @@ quick_error! {
     }
 }
 
+/// Which parts of a device line were recognized, for explaining why a line was rejected
+#[derive(Debug)]
+pub struct ParseDetail {
+    pub line: String,
+    /// Groups that matched, in order, with what they matched
+    pub matched: Vec<(&'static str, String)>,
+    /// Groups that didn't match. Only the first of these is known to be at fault, since the rest
+    /// come after it.
+    pub unmatched: Vec<&'static str>,
+}
+
+impl std::fmt::Display for ParseDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: ", self.line)?;
+        for (group, value) in &self.matched {
+            write!(f, "{group} matched {value:?}, ")?;
+        }
+        write!(f, "{} didn't match", self.unmatched.join(", "))
+    }
+}
+
+// the device line pattern, one named group at a time so a failed match can be narrowed down
+const DEVICE_LINE_GROUPS: [(&str, &str); 3] = [
+    (
+        "connection_name",
+        r"(?P<connection_name>[[[:word:]][[:punct:]]]+)",
+    ),
+    ("connection_state", r"\s+(?P<connection_state>[[:alpha:]]+)"),
+    ("details", r"(?P<details>.*)$"),
+];
+
 impl AdbDevice {
     pub fn parse(line: &str) -> Result<AdbDevice, Error> {
+        Self::parse_verbose(line).map_err(|detail| {
+            tracing::debug!("failed to parse device line {detail}");
+            Error::Parse(line.to_string())
+        })
+    }
+
+    /// Like [`AdbDevice::parse`], but reports which parts of the line were recognized on failure
+    pub fn parse_verbose(line: &str) -> Result<AdbDevice, ParseDetail> {
         lazy_static::lazy_static! {
-            static ref RE: Regex = Regex::new(r"(?x)
-            ^(?P<connection_name>[[[:word:]][[:punct:]]]+)
-            \s+
-            (?P<connection_state>[[:alpha:]]+)
-            \s
-            (?P<devpath>[[[:alnum:]]\-:]+)
-            (?P<adb_expanded>\s
-            product:(?P<product>.+)
-            \s
-            model:(?P<model>.+)
-            \s
-            device:(?P<device>.+)
-            \s
-            transport_id:(?P<transport_id>\d+))?").unwrap();
-        }
-        let captures = RE
-            .captures(line)
-            .ok_or_else(|| Error::Parse(line.to_string()))?;
+            // each successive regex adds one more group, ending with the full pattern
+            static ref PREFIXES: Vec<Regex> = (1..=DEVICE_LINE_GROUPS.len())
+                .map(|n| {
+                    let pattern: String = DEVICE_LINE_GROUPS[..n].iter().map(|(_, p)| *p).collect();
+                    Regex::new(&format!("^{pattern}")).unwrap()
+                })
+                .collect();
+        }
+
+        let captures = PREFIXES.last().unwrap().captures(line).ok_or_else(|| {
+            // the longest prefix that still matches tells which group is at fault
+            let matched = PREFIXES
+                .iter()
+                .rev()
+                .find_map(|re| re.captures(line))
+                .map(|captures| {
+                    DEVICE_LINE_GROUPS
+                        .iter()
+                        .filter_map(|(group, _)| {
+                            Some((*group, captures.name(group)?.as_str().to_string()))
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            ParseDetail {
+                line: line.to_string(),
+                unmatched: DEVICE_LINE_GROUPS[matched.len()..]
+                    .iter()
+                    .map(|(group, _)| *group)
+                    .collect(),
+                matched,
+            }
+        })?;
 
         let connection_name = captures["connection_name"].to_string();
         let connection_state = captures["connection_state"].to_string();
-        let devpath = captures["devpath"].to_string();
-
-        let live = if captures.name("adb_expanded").is_some() {
-            let product = captures["product"].to_string();
-            let model = captures["model"].to_string();
-            let device = captures["device"].to_string();
-            let transport_id = usize::from_str_radix(&captures["transport_id"].to_string(), 10)
-                .expect("Parsed as number, but did not convert to a number!");
-
-            Some(AdbDeviceLiveProperties {
-                product,
-                model,
-                device,
-                transport_id,
-            })
-        } else {
-            None
-        };
+
+        // the details are `key:value` pairs whose order and presence vary across adb versions,
+        // plus a devpath like `usb:1-1` which isn't present for every transport
+        let mut devpath = None;
+        let mut product = None;
+        let mut model = None;
+        let mut device = None;
+        let mut transport_id = None;
+        for detail in captures["details"].split_whitespace() {
+            match detail.split_once(':') {
+                Some(("product", value)) => product = Some(value.to_string()),
+                Some(("model", value)) => model = Some(value.to_string()),
+                Some(("device", value)) => device = Some(value.to_string()),
+                Some(("transport_id", value)) => transport_id = value.parse().ok(),
+                _ if devpath.is_none() => devpath = Some(detail.to_string()),
+                _ => tracing::debug!("ignoring unknown device detail {detail:?}"),
+            }
+        }
+
+        let live = product.map(|product| AdbDeviceLiveProperties {
+            product,
+            model: model.unwrap_or_default(),
+            device: device.unwrap_or_default(),
+            transport_id: transport_id.unwrap_or_default(),
+            android_version: None,
+            sdk: None,
+            serialno: None,
+        });
 
         Ok(AdbDevice {
             connection_name,
             properties: AdbDeviceProperties {
                 connection_state,
-                devpath,
+                devpath: devpath.unwrap_or_default(),
                 live,
+                favorite: false,
             },
         })
     }
 }
 
-pub async fn online_devices() -> Vec<Result<AdbDevice, crate::devices::Error>> {
-    let adb_devices = adb::devices();
-    let fastboot_devices = fastboot::devices();
-    let (adb_devices, fastboot_devices) = tokio::join!(adb_devices, fastboot_devices);
-    adb_devices.into_iter().chain(fastboot_devices).collect()
+/// Merges device lists from multiple sources into one entry per serial.
+///
+/// A device rebooting between adb and fastboot can briefly be reported by both, so later entries
+/// replace earlier ones with the same serial. Callers put adb devices last since track-devices
+/// reports changes as they happen, whereas fastboot is only polled.
+fn merge_devices(devices: impl IntoIterator<Item = AdbDevice>) -> Vec<AdbDevice> {
+    let mut merged: Vec<AdbDevice> = Vec::new();
+    for device in devices {
+        match merged
+            .iter_mut()
+            .find(|d| d.connection_name == device.connection_name)
+        {
+            Some(existing) => *existing = device,
+            None => merged.push(device),
+        }
+    }
+    merged
+}
+
+static SKIP_ADB: AtomicBool = AtomicBool::new(false);
+static SKIP_FASTBOOT: AtomicBool = AtomicBool::new(false);
+
+/// Leaves adb or fastboot out of device discovery entirely, e.g. when only one of them is in use
+pub fn set_skipped_sources(skip_adb: bool, skip_fastboot: bool) {
+    SKIP_ADB.store(skip_adb, Ordering::Relaxed);
+    SKIP_FASTBOOT.store(skip_fastboot, Ordering::Relaxed);
+}
+
+fn use_adb() -> bool {
+    !SKIP_ADB.load(Ordering::Relaxed)
+}
+
+fn use_fastboot() -> bool {
+    !SKIP_FASTBOOT.load(Ordering::Relaxed)
+}
+
+/// Queries adb and fastboot once for the devices that are currently connected
+pub async fn online_devices() -> Vec<AdbDevice> {
+    let (adb_devices, fastboot_devices) = tokio::join!(
+        async {
+            if use_adb() {
+                adb::devices(adb::transport()).await
+            } else {
+                Vec::new()
+            }
+        },
+        async {
+            if use_fastboot() {
+                fastboot::devices().await
+            } else {
+                Vec::new()
+            }
+        }
+    );
+    merge_devices(
+        fastboot_devices
+            .into_iter()
+            .chain(adb_devices)
+            .filter_map(Result::ok),
+    )
+}
+
+/// What can be done with a device adb reaches while it's in `state` but Android isn't running,
+/// for the states where logs and battery aren't available
+pub fn not_booted_hint(state: &str) -> Option<&'static str> {
+    match state {
+        "recovery" => Some("reboot it to read its logs or battery"),
+        "sideload" => Some("run `xadb sideload <package>` to install an update"),
+        _ => None,
+    }
 }
 
 fn poll_fastboot(
@@ -138,32 +333,163 @@ fn poll_fastboot(
     }
 }
 
+// `adb track-devices` exits when the adb server goes away (e.g. `adb kill-server`), so keep
+// re-spawning it. Spawning it also restarts the server.
+fn track_devices_reconnecting() -> impl Stream<Item = Vec<Result<AdbDevice, crate::devices::Error>>>
+{
+    const MIN_BACKOFF: Duration = Duration::from_millis(250);
+    const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+    stream! {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let mut devices = Box::pin(track_devices(adb::transport()));
+            while let Some(devices) = devices.next().await {
+                match devices {
+                    Ok(devices) => {
+                        backoff = MIN_BACKOFF;
+                        yield devices;
+                    }
+                    Err(err) => tracing::debug!("track-devices failed: {err:?}"),
+                }
+            }
+
+            // no devices are reachable until the server is back
+            yield Vec::new();
+
+            tracing::debug!("adb track-devices exited, reconnecting in {backoff:?}");
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
 pub fn query_devices_continuously(poll_rate: Duration) -> impl Stream<Item = Vec<AdbDevice>> {
     let mut fastboot_devices = Box::pin(poll_fastboot(poll_rate));
-    let mut adb_devices = Box::pin(track_devices().filter_map(Result::ok));
+    let mut adb_devices = Box::pin(track_devices_reconnecting());
 
     let mut current_fastboot = None;
     let mut current_adb = None;
     stream! {
         loop {
             tokio::select! {
-                devices = fastboot_devices.next() => {
+                devices = fastboot_devices.next(), if use_fastboot() => {
                     current_fastboot = devices;
                 },
-                devices = adb_devices.next() => {
+                devices = adb_devices.next(), if use_adb() => {
                     current_adb = devices;
                 }
             }
 
-            match (current_fastboot.as_ref(), current_adb.as_ref()) {
-                (Some(fastboot), Some(adb)) => {
-                    yield fastboot.iter().chain(adb.iter()).filter_map(|x| match x {
-                        Ok(devices) => Some(devices.clone()),
-                        Err(_) => None,
-                    }).collect();
+            // report as soon as either source has something, so a slow or missing fastboot
+            // doesn't hold back adb devices
+            if current_fastboot.is_some() || current_adb.is_some() {
+                yield merge_devices(
+                    current_fastboot
+                        .iter()
+                        .chain(current_adb.iter())
+                        .flatten()
+                        .filter_map(|x| x.as_ref().ok().cloned()),
+                );
+            }
+        }
+    }
+}
+
+/// Coalesces device lists arriving within `window` of each other into the most recent one, so a
+/// device flapping between states doesn't cause a redraw for every transition.
+pub fn debounce_devices(
+    devices: impl Stream<Item = Vec<AdbDevice>>,
+    window: Duration,
+) -> impl Stream<Item = Vec<AdbDevice>> {
+    stream! {
+        let mut devices = Box::pin(devices);
+        while let Some(mut latest) = devices.next().await {
+            // the window starts at the first change rather than the last, so constant churn still
+            // gets reported every `window`
+            let deadline = tokio::time::sleep(window);
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    next = devices.next() => match next {
+                        Some(next) => latest = next,
+                        None => break,
+                    },
+                    _ = &mut deadline => break,
+                }
+            }
+            yield latest;
+        }
+    }
+}
+
+/// A change to the set of connected devices
+pub enum DeviceEvent {
+    Connected(AdbDevice),
+    Disconnected(String),
+    /// The device is still there, but now in another state, e.g. `fastboot`
+    StateChanged(AdbDevice),
+}
+
+impl std::fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceEvent::Connected(device) => {
+                let product = device
+                    .properties
+                    .live
+                    .as_ref()
+                    .map_or("-", |live| live.product.as_str());
+                write!(
+                    f,
+                    "connected {} {} {product}",
+                    device.connection_name, device.properties.connection_state
+                )
+            }
+            DeviceEvent::Disconnected(serial) => write!(f, "disconnected {serial}"),
+            DeviceEvent::StateChanged(device) => write!(
+                f,
+                "state {} {}",
+                device.connection_name, device.properties.connection_state
+            ),
+        }
+    }
+}
+
+/// Turns snapshots of the connected devices into the changes between them. Devices in the first
+/// snapshot are reported as connected.
+pub fn device_events(
+    devices: impl Stream<Item = Vec<AdbDevice>>,
+) -> impl Stream<Item = DeviceEvent> {
+    stream! {
+        let mut devices = Box::pin(devices);
+        let mut previous: HashMap<String, AdbDevice> = HashMap::new();
+        while let Some(snapshot) = devices.next().await {
+            let current: HashMap<String, AdbDevice> = snapshot
+                .into_iter()
+                .map(|device| (device.connection_name.clone(), device))
+                .collect();
+
+            // sorted so events from one snapshot come out in a stable order
+            let mut serials: Vec<String> = current.keys().chain(previous.keys()).cloned().collect();
+            serials.sort();
+            serials.dedup();
+
+            for serial in serials {
+                match (previous.get(&serial), current.get(&serial)) {
+                    (None, Some(device)) => yield DeviceEvent::Connected(device.clone()),
+                    (Some(_), None) => yield DeviceEvent::Disconnected(serial),
+                    (Some(before), Some(after))
+                        if before.properties.connection_state
+                            != after.properties.connection_state =>
+                    {
+                        yield DeviceEvent::StateChanged(after.clone())
+                    }
+                    _ => {}
                 }
-                (_, _) => {}
             }
+
+            previous = current;
         }
     }
 }
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use crossterm::event::{Event, KeyCode, KeyEvent};
+use tokio::{pin, time::MissedTickBehavior};
+use tokio_stream::StreamExt;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout},
+    widgets::{Block, Borders, Paragraph},
+    Frame, Terminal,
+};
+
+use crate::{
+    commands::adb,
+    events::crossterm_event_stream,
+    theme,
+    widgets::{
+        log::{Log, LogState},
+        Control,
+    },
+};
+
+/// The `adb shell` command that dumps `service`, passing it `args`
+pub fn dumpsys_command(service: &str, args: &[String]) -> String {
+    shell_words::join(
+        ["dumpsys", service]
+            .into_iter()
+            .chain(args.iter().map(String::as_str)),
+    )
+}
+
+/// Shows the output of a `dumpsys` command in a pager as it arrives
+pub struct DumpsysApp {
+    command: String,
+    pager: LogState,
+    // what's been typed since `/`, while searching
+    prompt: Option<String>,
+    // the last search, repeated by n and N
+    search: Option<String>,
+    not_found: bool,
+}
+
+impl DumpsysApp {
+    pub fn new(serial: Option<&str>, command: String) -> Self {
+        let output = adb::shell(adb::transport(), serial, &command);
+        Self {
+            command,
+            pager: LogState::text(output),
+            prompt: None,
+            search: None,
+            not_found: false,
+        }
+    }
+
+    // selects the next match for the last search, or the previous one if `back` is set
+    fn find(&mut self, back: bool) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let found = if back {
+            self.pager.prev_containing(search)
+        } else {
+            self.pager.next_containing(search)
+        };
+        self.not_found = !found;
+    }
+
+    fn prompt_key(&mut self, key: KeyEvent) {
+        let Some(input) = self.prompt.as_mut() else {
+            return;
+        };
+
+        match key.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                // an empty search repeats the last one, like less
+                let input = self.prompt.take().unwrap();
+                if !input.is_empty() {
+                    self.search = Some(input);
+                }
+                self.find(false);
+            }
+            _ => {}
+        }
+    }
+
+    pub async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> std::io::Result<()> {
+        let key_events = crossterm_event_stream().filter_map(|event| match event {
+            Ok(Event::Key(key)) => Some(Ok(key)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        });
+        pin!(key_events);
+
+        // output can arrive much faster than it's worth redrawing
+        let mut frame = tokio::time::interval(Duration::from_millis(16));
+        frame.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut update = true;
+
+        loop {
+            tokio::select! {
+                key = key_events.next() => {
+                    let Some(key) = key else {
+                        return Ok(());
+                    };
+                    let key = key?;
+                    if self.prompt.is_some() {
+                        self.prompt_key(key);
+                        update = true;
+                        continue;
+                    }
+                    // until the next search says otherwise
+                    self.not_found = false;
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Up | KeyCode::Char('k') => self.pager.control(Control::Up),
+                        KeyCode::Down | KeyCode::Char('j') => self.pager.control(Control::Down),
+                        KeyCode::PageUp | KeyCode::Char('b') => self.pager.page(true),
+                        KeyCode::PageDown | KeyCode::Char(' ') => self.pager.page(false),
+                        KeyCode::Home | KeyCode::Char('g') => self.pager.control(Control::Top),
+                        KeyCode::End | KeyCode::Char('G') => self.pager.control(Control::Bottom),
+                        KeyCode::Char('/') => self.prompt = Some(String::new()),
+                        KeyCode::Char('n') => self.find(false),
+                        KeyCode::Char('N') => self.find(true),
+                        _ => {}
+                    }
+                    update = true;
+                },
+                _ = self.pager.poll() => update = true,
+                _ = frame.tick(), if update => {
+                    terminal.draw(|f| self.ui(f))?;
+                    update = false;
+                },
+            }
+        }
+    }
+
+    fn ui<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(f.size());
+
+        let lines = self.pager.logs().len();
+        let mut status = match self.pager.selected_index() {
+            Some(selected) => format!("line {} of {lines}", selected + 1),
+            None => format!("{lines} lines"),
+        };
+        if !self.pager.is_ended() {
+            status.push_str(", loading");
+        }
+        if let (true, Some(search)) = (self.not_found, &self.search) {
+            status.push_str(&format!(", {search:?} not found"));
+        }
+        let pager = Log::new().plain(true).block(
+            Block::default()
+                .title(format!("{} [{status}]", self.command))
+                .borders(Borders::ALL),
+        );
+
+        // the prompt only takes a line while it's open
+        if let Some(input) = &self.prompt {
+            f.render_stateful_widget(pager, chunks[0], &mut self.pager);
+            f.render_widget(Paragraph::new(format!("/{input}")), chunks[1]);
+        } else {
+            f.render_stateful_widget(pager, f.size(), &mut self.pager);
+        }
+        theme::strip_colors(f);
+    }
+}
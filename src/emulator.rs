@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use home::home_dir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+/// The console port of an emulator serial like `emulator-5554`
+pub fn console_port(serial: &str) -> Option<u16> {
+    serial.strip_prefix("emulator-")?.parse().ok()
+}
+
+/// Asks the emulator console for the name of the AVD an emulator is running, e.g. `Pixel_7_API_34`
+pub async fn emulator_name(serial: &str) -> Option<String> {
+    let port = console_port(serial)?;
+
+    // the console is local, so anything slower than this isn't going to answer
+    match tokio::time::timeout(Duration::from_secs(1), query_avd_name(port)).await {
+        Ok(Ok(name)) => name,
+        Ok(Err(err)) => {
+            tracing::debug!("emulator console on port {port} failed: {err:?}");
+            None
+        }
+        Err(_) => {
+            tracing::debug!("emulator console on port {port} timed out");
+            None
+        }
+    }
+}
+
+async fn query_avd_name(port: u16) -> std::io::Result<Option<String>> {
+    let (reader, mut writer) = TcpStream::connect(("127.0.0.1", port)).await?.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // the banner ends with "OK", and asks for the token in ~/.emulator_console_auth_token on
+    // emulators that require authentication
+    let banner = read_until_ok(&mut lines).await?;
+    if banner
+        .iter()
+        .any(|line| line.contains("Authentication required"))
+    {
+        let Some(token_path) = home_dir().map(|home| home.join(".emulator_console_auth_token"))
+        else {
+            return Ok(None);
+        };
+        let token = tokio::fs::read_to_string(token_path).await?;
+        writer
+            .write_all(format!("auth {}\n", token.trim()).as_bytes())
+            .await?;
+        read_until_ok(&mut lines).await?;
+    }
+
+    writer.write_all(b"avd name\n").await?;
+    let name = read_until_ok(&mut lines).await?.into_iter().next();
+
+    writer.write_all(b"quit\n").await?;
+    Ok(name)
+}
+
+// collects the lines of one console response, failing if the console reports an error
+async fn read_until_ok<R: tokio::io::AsyncBufRead + Unpin>(
+    lines: &mut tokio::io::Lines<R>,
+) -> std::io::Result<Vec<String>> {
+    let mut response = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim_end_matches('\r').to_string();
+        if line == "OK" {
+            return Ok(response);
+        }
+        if line.starts_with("KO") {
+            return Err(std::io::Error::other(line));
+        }
+        response.push(line);
+    }
+
+    Err(std::io::ErrorKind::UnexpectedEof.into())
+}
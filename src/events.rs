@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use crossterm::event::Event;
+use futures::Stream;
+
+/// Reads terminal events on a blocking thread so they can be awaited alongside other streams
+pub fn crossterm_event_stream() -> impl Stream<Item = crossterm::Result<Event>> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::task::spawn_blocking(move || loop {
+        // the receiver goes away when a TUI hands the terminal over to another one
+        if crossterm::event::poll(Duration::from_millis(100)).unwrap_or(false)
+            && tx.send(crossterm::event::read()).is_err()
+        {
+            break;
+        }
+
+        if tx.is_closed() {
+            break;
+        }
+    });
+
+    return tokio_stream::wrappers::UnboundedReceiverStream::from(rx);
+}
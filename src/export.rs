@@ -0,0 +1,114 @@
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    anonymize::display_serial,
+    cache::Cache,
+    devices::{AdbDevice, AdbDeviceProperties},
+};
+
+const DEVICE_COLUMNS: [&str; 8] = [
+    "serial",
+    "connection_state",
+    "product",
+    "model",
+    "device",
+    "transport_id",
+    // xadb doesn't name devices yet, so this is always empty
+    "alias",
+    "last_seen",
+];
+
+/// Quotes a CSV field if it contains a comma, quote or line break, doubling any quotes in it
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::from(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::from(field)
+    }
+}
+
+fn write_csv_row(out: &mut impl Write, fields: &[impl AsRef<str>]) -> io::Result<()> {
+    let fields: Vec<Cow<str>> = fields.iter().map(|f| csv_field(f.as_ref())).collect();
+    // RFC 4180 ends rows with CRLF, which spreadsheets expect
+    write!(out, "{}\r\n", fields.join(","))
+}
+
+fn device_row(
+    serial: &str,
+    properties: &AdbDeviceProperties,
+    state: &str,
+    last_seen: Option<i64>,
+) -> Vec<String> {
+    let live = properties.live.as_ref();
+    let last_seen = last_seen
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .map(|time| time.to_rfc3339());
+    vec![
+        display_serial(serial),
+        state.to_string(),
+        live.map(|l| l.product.clone()).unwrap_or_default(),
+        live.map(|l| l.model.clone()).unwrap_or_default(),
+        live.map(|l| l.device.clone()).unwrap_or_default(),
+        live.map(|l| l.transport_id.to_string()).unwrap_or_default(),
+        String::new(),
+        last_seen.unwrap_or_default(),
+    ]
+}
+
+// a row for each connected device, then each cached one that isn't connected, in the order of
+// `DEVICE_COLUMNS`
+fn device_rows(online: &[AdbDevice], cache: &Cache) -> Vec<Vec<String>> {
+    let now = Utc::now().timestamp();
+    let mut rows: Vec<Vec<String>> = online
+        .iter()
+        .map(|d| {
+            device_row(
+                &d.connection_name,
+                &d.properties,
+                &d.properties.connection_state,
+                Some(now),
+            )
+        })
+        .collect();
+
+    let mut offline: Vec<_> = cache
+        .devices
+        .iter()
+        .filter(|(serial, _)| !online.iter().any(|d| d.connection_name == **serial))
+        .collect();
+    offline.sort_by_key(|(serial, _)| *serial);
+    // "offline" is a state adb reports for connected devices, so it would be misleading here
+    rows.extend(offline.into_iter().map(|(serial, properties)| {
+        device_row(
+            serial,
+            properties,
+            "disconnected",
+            cache.last_seen.get(serial).copied(),
+        )
+    }));
+    rows
+}
+
+/// Prints connected and cached devices, as CSV with a header row if `csv` is set, or otherwise as
+/// tab separated columns
+pub fn print_devices(online: &[AdbDevice], cache: &Cache, csv: bool) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    let rows = device_rows(online, cache);
+
+    if csv {
+        write_csv_row(&mut out, &DEVICE_COLUMNS)?;
+        for row in &rows {
+            write_csv_row(&mut out, row)?;
+        }
+    } else {
+        for row in &rows {
+            writeln!(out, "{}", row.join("\t"))?;
+        }
+    }
+    out.flush()
+}
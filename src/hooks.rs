@@ -0,0 +1,32 @@
+use tokio::process::Command;
+
+use crate::{
+    commands::adb::{is_dry_run, print_command, spawn},
+    devices::DeviceEvent,
+};
+
+/// Runs the `on-connect` command from the config when `event` is a device becoming usable over
+/// adb, either connecting booted or finishing booting. `{serial}` in the command is replaced with
+/// the device's serial.
+///
+/// The command runs in the background through `sh -c`, so it can't hold up later events.
+pub fn on_device_event(command: &str, event: &DeviceEvent) {
+    let device = match event {
+        DeviceEvent::Connected(device) | DeviceEvent::StateChanged(device) => device,
+        DeviceEvent::Disconnected(_) => return,
+    };
+    if device.properties.connection_state != "device" {
+        return;
+    }
+
+    let command = command.replace("{serial}", &shell_words::quote(&device.connection_name));
+    let args = ["-c".to_string(), command];
+    if is_dry_run() {
+        print_command("sh", &args);
+        return;
+    }
+
+    if let Err(err) = spawn(Command::new("sh").args(&args)) {
+        tracing::debug!("failed to run on-connect command: {err:?}");
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+};
+use tui::style::Modifier;
+
+use crate::widgets::log::SourceLink;
+
+/// Whether the terminal is likely to understand OSC 8 hyperlinks. There's no way to query this, so
+/// it's guessed from the environment the way other tools do. `FORCE_HYPERLINK` overrides the guess.
+pub fn supported() -> bool {
+    let var = |name| std::env::var(name).ok();
+
+    if let Some(force) = var("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+
+    if var("DOMTERM").is_some() || var("WT_SESSION").is_some() || var("KONSOLE_VERSION").is_some() {
+        return true;
+    }
+
+    if let Some(version) = var("VTE_VERSION").and_then(|v| v.parse::<u32>().ok()) {
+        return version >= 5000;
+    }
+
+    matches!(
+        var("TERM_PROGRAM").as_deref(),
+        Some("iTerm.app" | "WezTerm" | "vscode" | "Hyper" | "ghostty")
+    ) || matches!(
+        var("TERM").as_deref(),
+        Some("xterm-kitty" | "alacritty" | "foot" | "xterm-ghostty")
+    )
+}
+
+/// Fills in `{file}` and `{line}` in a URL template like `idea://open?file={file}&line={line}`
+fn link_url(url_format: &str, link: &SourceLink) -> String {
+    url_format
+        .replace("{file}", &link.file)
+        .replace("{line}", &link.line.to_string())
+}
+
+/// Redraws each link over the frame that was just drawn, wrapped in OSC 8 escapes.
+///
+/// tui measures cells by their printable characters, which the escapes would throw off, so the
+/// links are written straight to the terminal instead of through the buffer. Each cell is linked
+/// on its own so that a later partial redraw can't leave a link open.
+pub fn write_links(out: &mut impl Write, links: &[SourceLink], url_format: &str) -> io::Result<()> {
+    for link in links {
+        let url = link_url(url_format, link);
+        queue!(out, MoveTo(link.x, link.y))?;
+        for cell in &link.cells {
+            queue!(
+                out,
+                SetAttribute(Attribute::Reset),
+                SetForegroundColor(cell.fg.into()),
+                SetBackgroundColor(cell.bg.into()),
+            )?;
+            for (modifier, attribute) in [
+                (Modifier::BOLD, Attribute::Bold),
+                (Modifier::DIM, Attribute::Dim),
+                (Modifier::ITALIC, Attribute::Italic),
+                (Modifier::UNDERLINED, Attribute::Underlined),
+                (Modifier::REVERSED, Attribute::Reverse),
+            ] {
+                if cell.modifier.contains(modifier) {
+                    queue!(out, SetAttribute(attribute))?;
+                }
+            }
+            queue!(
+                out,
+                Print(format!("\x1b]8;;{url}\x1b\\{}\x1b]8;;\x1b\\", cell.symbol))
+            )?;
+        }
+    }
+
+    // tui assumes the defaults are in effect at the start of each frame
+    queue!(
+        out,
+        SetAttribute(Attribute::Reset),
+        SetForegroundColor(Color::Reset),
+        SetBackgroundColor(Color::Reset),
+    )?;
+    out.flush()
+}
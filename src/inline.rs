@@ -0,0 +1,106 @@
+use std::io::{self, Stderr, Write};
+
+use crossterm::{
+    cursor::{self, MoveTo},
+    execute,
+    terminal::{self, Clear, ClearType},
+};
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    buffer::Cell,
+    layout::Rect,
+};
+
+/// Draws to a band of lines starting at `top` rather than to the whole screen, so the rest of the
+/// terminal's contents stay visible.
+///
+/// tui reports positions relative to the viewport when drawing, so they're shifted down here.
+pub struct InlineBackend {
+    backend: CrosstermBackend<Stderr>,
+    top: u16,
+    height: u16,
+}
+
+impl InlineBackend {
+    /// Makes room for `height` lines below the cursor, scrolling the terminal if needed
+    pub fn below_cursor(height: u16) -> io::Result<InlineBackend> {
+        let (_, rows) = terminal::size()?;
+        let height = height.min(rows);
+
+        let mut stderr = io::stderr();
+        write!(stderr, "{}", "\n".repeat(height as usize))?;
+        stderr.flush()?;
+        let (_, y) = cursor::position()?;
+
+        Ok(InlineBackend {
+            backend: CrosstermBackend::new(stderr),
+            top: y.saturating_sub(height),
+            height,
+        })
+    }
+
+    /// The area to draw to, relative to `top`
+    pub fn viewport(&self) -> io::Result<Rect> {
+        let (width, _) = terminal::size()?;
+        Ok(Rect::new(0, 0, width, self.height))
+    }
+
+    /// Clears the lines drawn to, leaving the cursor at the first of them
+    pub fn clear_lines(&mut self) -> io::Result<()> {
+        execute!(
+            self.backend,
+            MoveTo(0, self.top),
+            Clear(ClearType::FromCursorDown)
+        )
+    }
+}
+
+impl Write for InlineBackend {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.backend.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(&mut self.backend)
+    }
+}
+
+impl Backend for InlineBackend {
+    fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        let top = self.top;
+        self.backend
+            .draw(content.map(|(x, y, cell)| (x, y + top, cell)))
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        self.backend.hide_cursor()
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        self.backend.show_cursor()
+    }
+
+    fn get_cursor(&mut self) -> io::Result<(u16, u16)> {
+        let (x, y) = self.backend.get_cursor()?;
+        Ok((x, y.saturating_sub(self.top)))
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        self.backend.set_cursor(x, y + self.top)
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.clear_lines()
+    }
+
+    fn size(&self) -> io::Result<Rect> {
+        self.backend.size()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Backend::flush(&mut self.backend)
+    }
+}
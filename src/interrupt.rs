@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Yields from `stream` until it ends or Ctrl-C is pressed. Streaming commands print through this
+/// so an interrupt lands between items instead of tearing down the process mid-write.
+pub fn until_ctrl_c<S: Stream>(stream: S) -> impl Stream<Item = S::Item> {
+    stream! {
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+        tokio::pin!(stream);
+
+        loop {
+            tokio::select! {
+                item = stream.next() => match item {
+                    Some(item) => yield item,
+                    None => break,
+                },
+                _ = &mut ctrl_c => {
+                    INTERRUPTED.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Whether a stream from `until_ctrl_c` was stopped by Ctrl-C
+pub fn was_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
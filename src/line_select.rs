@@ -0,0 +1,63 @@
+use std::{error::Error, io::Write};
+
+use crate::{cache::Cache, devices::online_devices};
+
+/// Lists the connected devices on stderr and reads the number of one from stdin, for when the
+/// terminal can't be taken over. Returns `None` if nothing was picked.
+pub async fn select_by_number(no_cache: bool) -> Result<Option<String>, Box<dyn Error>> {
+    let devices = online_devices().await;
+    if devices.is_empty() {
+        return Err("no devices connected".into());
+    }
+
+    // stdout is captured by the shell integration, so the list goes to stderr
+    let mut stderr = std::io::stderr();
+    for (i, device) in devices.iter().enumerate() {
+        let properties = &device.properties;
+        match &properties.live {
+            Some(live) => writeln!(
+                stderr,
+                "{:>3}) {} {} {} ({})",
+                i + 1,
+                device.connection_name,
+                live.product,
+                live.model,
+                properties.connection_state
+            )?,
+            None => writeln!(
+                stderr,
+                "{:>3}) {} ({})",
+                i + 1,
+                device.connection_name,
+                properties.connection_state
+            )?,
+        }
+    }
+
+    // nothing else is running, so blocking on stdin is fine
+    let mut lines = std::io::stdin().lines();
+    let serial = loop {
+        write!(stderr, "device number (empty to cancel): ")?;
+        stderr.flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(None);
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        match line.parse::<usize>() {
+            Ok(n) if (1..=devices.len()).contains(&n) => {
+                break devices[n - 1].connection_name.clone();
+            }
+            _ => writeln!(stderr, "pick a number from 1 to {}", devices.len())?,
+        }
+    };
+
+    let mut cache = Cache::load(no_cache).await?;
+    cache.last_selected = Some(serial.clone());
+    cache.persist().await?;
+    Ok(Some(serial))
+}
@@ -1,21 +1,39 @@
-use std::{io::Stderr, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Stderr, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
+use chrono::NaiveTime;
 use crossterm::event::{Event, KeyCode, KeyEvent};
-use futures::Stream;
 use quick_error::quick_error;
-use tokio::pin;
+use tokio::{
+    pin,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
 use tokio_stream::StreamExt;
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout},
-    widgets::{Block, Borders},
+    widgets::{Block, BorderType, Borders, Paragraph},
     Frame, Terminal,
 };
 
 use crate::{
+    cache::{xadb_dir, Cache, UiPrefs},
+    cli::LogcatArgs,
+    commands::adb::{self, format_message_bytes, log_id_name, LogFormat, LogLevel, LogcatOptions},
+    device_select::{DeviceSelectOptions, SelectOutcome},
+    events::crossterm_event_stream,
+    hyperlink,
+    interrupt::until_ctrl_c,
+    serial::{ensure_booted, resolve_serial, Error as SerialError},
+    theme,
     widgets::{
         fps_overlay::{FpsOverlay, FpsOverlayState},
-        log::LogState,
+        log::{LogState, TimeFormat},
+        shell::{poll_overlay, ShellOverlay, ShellOverlayState},
     },
     widgets::{
         log::Log,
@@ -30,46 +48,127 @@ quick_error! {
         Io(err: crate::io::Error) {
             from()
         }
+        Cache(err: crate::cache::Error) {
+            from()
+        }
         Decode(err: crate::commands::adb::LogcatDecodeError) {
             from()
         }
         DeviceSelect(err: crate::device_select::Error) {
             from()
         }
+        Serial(err: crate::serial::Error) {
+            from()
+            display("{}", err)
+        }
+        NoDevice {
+            display("no device to read logs from; set ANDROID_SERIAL or pick one with `xadb list`")
+        }
     }
 }
 
-fn crossterm_event_stream() -> impl Stream<Item = crossterm::Result<Event>> {
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+/// How the log view was left
+pub enum LogcatOutcome {
+    /// Quit with q
+    Quit,
+    /// No device was picked in the selector
+    Cancelled,
+    /// adb stopped sending the device's log, usually because it was disconnected
+    Disconnected(String),
+}
 
-    tokio::task::spawn_blocking(move || loop {
-        if crossterm::event::poll(Duration::from_millis(100)).unwrap_or(false) {
-            tx.send(crossterm::event::read()).unwrap();
-        }
+// a single line of input collected in place of the status bar
+enum Prompt {
+    JumpToTime,
+}
 
-        if tx.is_closed() {
-            break;
-        }
-    });
+// the name of a process as `ps` reported it, or why it couldn't
+struct ProcessLookup {
+    serial: String,
+    pid: i32,
+    name: std::io::Result<Option<String>>,
+}
 
-    return tokio_stream::wrappers::UnboundedReceiverStream::from(rx);
+impl Prompt {
+    fn label(&self) -> &'static str {
+        match self {
+            Prompt::JumpToTime => "jump to time (HH:MM:SS): ",
+        }
+    }
 }
 
 pub struct LogcatApp {
+    args: LogcatArgs,
+    use_default_serial: bool,
+    select_options: DeviceSelectOptions,
+    prompt: Option<(Prompt, String)>,
+    shell: Option<ShellOverlayState>,
+    // commands run in the shell overlay, kept while it's closed
+    shell_history: VecDeque<String>,
     zoom: bool,
+    compact: bool,
+    multiline: bool,
+    show_pid: bool,
+    show_tid: bool,
+    line_numbers: bool,
     debug: bool,
+    // only set when the terminal looks like it can show hyperlinks
+    editor_url: Option<String>,
     log: Option<LogState>,
-    status_bar: StatusBarState,
+    // the right-hand pane with `--split`, showing the second buffer
+    split: Option<LogState>,
+    // whether keys act on `split` rather than `log`
+    focus_split: bool,
+    // keep the panes at the same time as each other
+    sync_scroll: bool,
+    // a short message shown in place of the status bar until it expires
+    toast: Option<(String, Instant)>,
+    // names of the processes looked up so far, by serial and pid
+    process_names: HashMap<(String, i32), String>,
+    // `ps` runs in the background so the log keeps up while adb answers, and sends back what it
+    // found here
+    lookups: UnboundedSender<ProcessLookup>,
+    lookup_results: UnboundedReceiver<ProcessLookup>,
+    status_bar: Option<StatusBarState>,
     fps_overlay: FpsOverlayState,
 }
 
+const TARGET_FPS: u32 = 60;
+
+// long enough to read a file path
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+
 impl LogcatApp {
-    pub fn new() -> Self {
+    pub fn new(
+        args: LogcatArgs,
+        use_default_serial: bool,
+        select_options: DeviceSelectOptions,
+    ) -> Self {
+        let (lookups, lookup_results) = unbounded_channel();
         Self {
+            compact: args.compact.unwrap_or_default(),
+            multiline: args.multiline.unwrap_or_default(),
+            show_pid: args.show_pid.unwrap_or_default(),
+            show_tid: args.show_tid.unwrap_or_default(),
+            line_numbers: args.line_numbers.unwrap_or_default(),
+            editor_url: args.editor_url.clone().filter(|_| hyperlink::supported()),
+            args,
+            use_default_serial,
+            select_options,
+            prompt: None,
+            shell: None,
+            shell_history: VecDeque::new(),
             zoom: false,
             debug: false,
             log: Default::default(),
-            status_bar: StatusBarState::new(),
+            split: None,
+            focus_split: false,
+            sync_scroll: false,
+            toast: None,
+            process_names: HashMap::new(),
+            lookups,
+            lookup_results,
+            status_bar: None,
             fps_overlay: FpsOverlayState::new(128),
         }
     }
@@ -77,24 +176,86 @@ impl LogcatApp {
     pub async fn run(
         &mut self,
         terminal: &mut Terminal<CrosstermBackend<Stderr>>,
-    ) -> Result<(), Error> {
-        let serial = match std::env::var("ANDROID_SERIAL") {
-            Ok(serial) => serial,
-            _ => {
+    ) -> Result<LogcatOutcome, Error> {
+        let serial = if self.args.pick {
+            None
+        } else {
+            match resolve_serial(self.use_default_serial).await {
+                Ok(serial) => serial,
+                // a stale ANDROID_SERIAL just means a device needs to be picked
+                Err(SerialError::NotFound(serial, _)) => {
+                    tracing::debug!("ANDROID_SERIAL={serial} isn't connected, showing selector");
+                    None
+                }
+                Err(err) => return Err(err.into()),
+            }
+        };
+
+        let serial = match serial {
+            Some(serial) => serial,
+            None => {
                 let mut device_list =
-                    crate::device_select::DeviceSelectApp::load_initial_state().await?;
+                    crate::device_select::DeviceSelectApp::load_initial_state(&self.select_options)
+                        .await?;
 
                 match device_list
                     .run(terminal, std::time::Duration::from_millis(250))
                     .await?
                 {
-                    Some(serial) => serial,
-                    None => return Ok(()),
+                    SelectOutcome::Selected(serial) | SelectOutcome::ViewLogs(serial) => serial,
+                    SelectOutcome::Cancelled => return Ok(LogcatOutcome::Cancelled),
                 }
             }
         };
 
-        self.log = Some(LogState::new(serial.as_str()));
+        self.view(terminal, serial).await
+    }
+
+    /// Shows the log of `serial`, without picking a device first
+    pub async fn view(
+        &mut self,
+        terminal: &mut Terminal<CrosstermBackend<Stderr>>,
+        serial: String,
+    ) -> Result<LogcatOutcome, Error> {
+        // checked once the device is known, so a device in recovery can still be picked past
+        ensure_booted(Some(&serial)).await?;
+
+        let prefs = if self.args.reset_prefs {
+            UiPrefs::default()
+        } else {
+            Cache::load(self.select_options.no_cache)
+                .await?
+                .per_device_ui
+                .remove(&serial)
+                .unwrap_or_default()
+        };
+        self.restore_prefs(&prefs);
+
+        let options = self.logcat_options();
+        let mut log = if let [left, right] = self.args.split[..] {
+            let pane = |lid| {
+                LogState::new(
+                    serial.as_str(),
+                    &LogcatOptions {
+                        log_ids: vec![lid],
+                        ..options.clone()
+                    },
+                )
+            };
+            self.split = Some(pane(right));
+            pane(left)
+        } else {
+            LogState::new(serial.as_str(), &options)
+        };
+        log.set_buffer_filter(self.args.buffers.clone());
+        if prefs.paused {
+            log.toggle_follow();
+            if let Some(split) = &mut self.split {
+                split.toggle_follow();
+            }
+        }
+        self.log = Some(log);
+        self.status_bar = Some(StatusBarState::new(Some(serial.clone())));
 
         let poll_events = crossterm_event_stream().filter_map(|event| {
             if let Ok(Event::Key(key)) = event {
@@ -105,71 +266,222 @@ impl LogcatApp {
         });
         pin!(poll_events);
 
-        let target_fps = 60;
-        let mut interval = tokio::time::interval(Duration::from_micros(
-            (1000000.0 / target_fps as f64) as u64,
-        ));
-        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // frames are drawn on a steady beat, whatever the events in between. a frame that's missed
+        // while busy is dropped rather than made up for with a burst of redraws
+        let mut frame = tokio::time::interval(Duration::from_secs(1) / TARGET_FPS);
+        frame.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
         let mut update = false;
 
+        // the logs per second fall off as the window moves on, even with nothing new to draw
+        let mut meter = tokio::time::interval(Duration::from_secs(1));
+
         loop {
             enum Event {
                 KeyEvent(KeyEvent),
                 WidgetUpdate,
+                ProcessLookup(ProcessLookup),
                 Tick,
             }
 
+            let toast_expiry = self
+                .toast
+                .as_ref()
+                .map(|(_, shown)| *shown + TOAST_DURATION);
             let next = tokio::select! {
+                // checked first so a busy log can't hold back a frame that's due
+                biased;
+
+                _ = frame.tick(), if update => {
+                    Event::Tick
+                },
                 key = poll_events.next() => {
                     Event::KeyEvent(key.unwrap())
                 },
-                _ = interval.tick(), if update => {
-                    Event::Tick
-                },
                 _ = self.log.as_mut().unwrap().poll() => {
                     Event::WidgetUpdate
                 }
-                _ = self.status_bar.poll() => {
+                _ = poll_split(&mut self.split) => {
+                    Event::WidgetUpdate
+                }
+                _ = self.status_bar.as_mut().unwrap().poll() => {
                     Event::WidgetUpdate
                 },
+                _ = poll_overlay(&mut self.shell) => {
+                    Event::WidgetUpdate
+                },
+                // never ends, since `self.lookups` is a sender
+                lookup = self.lookup_results.recv() => {
+                    Event::ProcessLookup(lookup.unwrap())
+                },
+                _ = meter.tick() => {
+                    Event::WidgetUpdate
+                },
+                // redraw once the toast expires. the sleep is created even when the branch is
+                // disabled, so it needs some deadline
+                _ = tokio::time::sleep_until(toast_expiry.unwrap_or_else(Instant::now).into()), if toast_expiry.is_some() => {
+                    self.toast = None;
+                    Event::WidgetUpdate
+                }
             };
 
             match next {
+                Event::ProcessLookup(lookup) => {
+                    let message = self.describe_process(lookup);
+                    self.toast = Some((message, Instant::now()));
+                    update = true;
+                }
+                Event::KeyEvent(key) if self.shell.is_some() => {
+                    if !self.shell.as_mut().unwrap().key(key) {
+                        self.shell_history = self.shell.take().unwrap().into_history();
+                    }
+                    update = true;
+                }
+                Event::KeyEvent(key) if self.prompt.is_some() => {
+                    self.prompt_key(key);
+                    self.sync_panes();
+                    update = true;
+                }
                 Event::KeyEvent(key) => match key.code {
                     KeyCode::Char('z') => {
                         self.zoom = !self.zoom;
                         update = true;
                     }
+                    KeyCode::Char('c') => {
+                        self.compact = !self.compact;
+                        update = true;
+                    }
+                    KeyCode::Char('r') => {
+                        self.args.time_format =
+                            Some(self.args.time_format.unwrap_or_default().toggle());
+                        update = true;
+                    }
+                    KeyCode::Char('m') => {
+                        self.multiline = !self.multiline;
+                        update = true;
+                    }
+                    KeyCode::Char('p') => {
+                        self.show_pid = !self.show_pid;
+                        update = true;
+                    }
+                    KeyCode::Char('P') => {
+                        self.show_tid = !self.show_tid;
+                        update = true;
+                    }
+                    KeyCode::Char('n') => {
+                        self.line_numbers = !self.line_numbers;
+                        update = true;
+                    }
                     KeyCode::Char('k') => {
-                        self.log.as_mut().unwrap().control(Control::Up);
+                        self.focused().control(Control::Up);
+                        self.sync_panes();
                         update = true;
                     }
                     KeyCode::Char('j') => {
-                        self.log.as_mut().unwrap().control(Control::Down);
+                        self.focused().control(Control::Down);
+                        self.sync_panes();
+                        update = true;
+                    }
+                    KeyCode::Char('f') => {
+                        self.focused().toggle_follow();
+                        self.sync_panes();
+                        update = true;
+                    }
+                    KeyCode::Char(']') => {
+                        let level = self.jump_level();
+                        self.focused().next_at_or_above(level);
+                        self.sync_panes();
+                        update = true;
+                    }
+                    KeyCode::Char('[') => {
+                        let level = self.jump_level();
+                        self.focused().prev_at_or_above(level);
+                        self.sync_panes();
                         update = true;
                     }
                     KeyCode::Home => {
-                        self.log.as_mut().unwrap().control(Control::Top);
+                        self.focused().control(Control::Top);
+                        self.sync_panes();
                         update = true;
                     }
                     KeyCode::End => {
-                        self.log.as_mut().unwrap().control(Control::Bottom);
+                        self.focused().control(Control::Bottom);
+                        self.sync_panes();
+                        update = true;
+                    }
+                    KeyCode::Tab if self.split.is_some() => {
+                        self.focus_split = !self.focus_split;
+                        update = true;
+                    }
+                    KeyCode::Char('S') if self.split.is_some() => {
+                        self.sync_scroll = !self.sync_scroll;
+                        self.sync_panes();
+                        update = true;
+                    }
+                    KeyCode::Char('x') => {
+                        self.focused().clear();
+                        update = true;
+                    }
+                    KeyCode::Char('w') => {
+                        let message = match self.dump_logs(&serial).await {
+                            Ok(path) => format!("saved log to {}", path.display()),
+                            Err(err) => format!("couldn't save log: {err}"),
+                        };
+                        self.toast = Some((message, Instant::now()));
+                        update = true;
+                    }
+                    KeyCode::Char('i') => {
+                        if let Some(message) = self.describe_selected_process(&serial) {
+                            self.toast = Some((message, Instant::now()));
+                            update = true;
+                        }
+                    }
+                    KeyCode::Char('t') => {
+                        self.prompt = Some((Prompt::JumpToTime, String::new()));
+                        update = true;
+                    }
+                    KeyCode::Char(':') => {
+                        self.shell = Some(ShellOverlayState::new(
+                            Some(serial.clone()),
+                            std::mem::take(&mut self.shell_history),
+                        ));
+                        update = true;
+                    }
+                    KeyCode::Char('.') => {
+                        let mut shell = ShellOverlayState::new(
+                            Some(serial.clone()),
+                            std::mem::take(&mut self.shell_history),
+                        );
+                        if shell.rerun_last() {
+                            self.shell = Some(shell);
+                        } else {
+                            self.shell_history = shell.into_history();
+                            self.toast =
+                                Some(("no shell command to re-run".to_string(), Instant::now()));
+                        }
                         update = true;
                     }
                     KeyCode::Char('?') => {
                         self.debug = !self.debug;
                         update = true;
                     }
-                    KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('q') => {
+                        self.save_prefs(&serial).await?;
+                        return Ok(LogcatOutcome::Quit);
+                    }
                     _ => {}
                 },
+                Event::WidgetUpdate if self.log.as_ref().unwrap().is_ended() => {
+                    self.save_prefs(&serial).await?;
+                    return Ok(LogcatOutcome::Disconnected(serial));
+                }
                 Event::WidgetUpdate => {
                     update = true;
                 }
                 Event::Tick => {
                     if update {
                         terminal.draw(|f| self.ui(f)).unwrap();
+                        self.write_links(terminal)?;
                         update = false;
                     }
                 }
@@ -177,6 +489,265 @@ impl LogcatApp {
         }
     }
 
+    fn write_links(&self, terminal: &mut Terminal<CrosstermBackend<Stderr>>) -> Result<(), Error> {
+        // the shell overlay would be drawn over
+        if let (Some(url), None) = (&self.editor_url, &self.shell) {
+            for pane in self.log.iter().chain(&self.split) {
+                hyperlink::write_links(terminal.backend_mut(), pane.source_links(), url)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the pane keys act on
+    fn focused(&mut self) -> &mut LogState {
+        match (&mut self.split, self.focus_split) {
+            (Some(split), true) => split,
+            _ => self.log.as_mut().unwrap(),
+        }
+    }
+
+    fn focused_ref(&self) -> &LogState {
+        match (&self.split, self.focus_split) {
+            (Some(split), true) => split,
+            _ => self.log.as_ref().unwrap(),
+        }
+    }
+
+    // with sync scrolling, moves the other pane to the time of the focused one
+    fn sync_panes(&mut self) {
+        let (Some(log), Some(split), true) = (&mut self.log, &mut self.split, self.sync_scroll)
+        else {
+            return;
+        };
+        let (from, to) = if self.focus_split {
+            (split, log)
+        } else {
+            (log, split)
+        };
+
+        match from.selected_timestamp() {
+            Some(timestamp) => to.select_timestamp(timestamp),
+            None => to.control(Control::Bottom),
+        }
+        if from.is_following() != to.is_following() {
+            to.toggle_follow();
+        }
+    }
+
+    fn prompt_key(&mut self, key: KeyEvent) {
+        let Some((prompt, input)) = self.prompt.as_mut() else {
+            return;
+        };
+
+        match key.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => match prompt {
+                Prompt::JumpToTime => {
+                    // leave the prompt open on bad input so it can be corrected
+                    if let Ok(time) = NaiveTime::parse_from_str(input, "%H:%M:%S") {
+                        // the time is read on the clock the log is shown with
+                        let exact = match self.args.time_format.unwrap_or_default() {
+                            TimeFormat::Local => self.focused().jump_to_local_time(time),
+                            _ => {
+                                self.focused().jump_to_time(time);
+                                true
+                            }
+                        };
+                        if !exact {
+                            let message = format!(
+                                "{time} is skipped or repeated by DST here, read it as UTC"
+                            );
+                            self.toast = Some((message, Instant::now()));
+                        }
+                        self.prompt = None;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // saved prefs fill in whatever the flags, environment and config.toml leave unset
+    fn restore_prefs(&mut self, prefs: &UiPrefs) {
+        self.compact = self.args.compact.unwrap_or(prefs.compact);
+        self.multiline = self.args.multiline.unwrap_or(prefs.multiline);
+        self.show_pid = self.args.show_pid.unwrap_or(prefs.show_pid);
+        self.show_tid = self.args.show_tid.unwrap_or(prefs.show_tid);
+        self.line_numbers = self.args.line_numbers.unwrap_or(prefs.line_numbers);
+        self.args.color_by = self.args.color_by.or(prefs.color_by);
+        self.args.time_format = self.args.time_format.or(prefs.time_format);
+        // each pane of --split already reads a single buffer, which a saved filter could hide
+        if self.args.buffers.is_empty() && self.args.split.is_empty() {
+            self.args.buffers = prefs.buffers.clone();
+        }
+    }
+
+    async fn save_prefs(&self, serial: &str) -> Result<(), Error> {
+        let mut prefs = UiPrefs {
+            compact: self.compact,
+            multiline: self.multiline,
+            show_pid: self.show_pid,
+            show_tid: self.show_tid,
+            line_numbers: self.line_numbers,
+            color_by: self.args.color_by,
+            time_format: self.args.time_format,
+            buffers: self.args.buffers.clone(),
+            paused: !self.log.as_ref().unwrap().is_following(),
+        };
+
+        // reload in case another xadb changed the cache in the meantime
+        let mut cache = Cache::load(self.select_options.no_cache).await?;
+        // --split never uses the filter, so the one saved by an unsplit session is kept
+        if !self.args.split.is_empty() {
+            if let Some(saved) = cache.per_device_ui.get(serial) {
+                prefs.buffers = saved.buffers.clone();
+            }
+        }
+        cache.per_device_ui.insert(serial.to_string(), prefs);
+        Ok(cache.persist().await?)
+    }
+
+    // writes every message kept so far to a new file in $XADB_DIR/dumps, in --format
+    async fn dump_logs(&self, serial: &str) -> std::io::Result<PathBuf> {
+        let dumps = xadb_dir().join("dumps");
+        tokio::fs::create_dir_all(&dumps).await?;
+
+        // serials of network devices contain ':', which isn't allowed in file names everywhere
+        let serial: String = serial
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = dumps.join(format!(
+            "{serial}-{}.txt",
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        ));
+
+        let mut contents = Vec::new();
+        for message in self.focused_ref().logs() {
+            contents.extend_from_slice(&format_message_bytes(message, self.args.format));
+            contents.push(b'\n');
+        }
+        tokio::fs::write(&path, contents).await?;
+        Ok(path)
+    }
+
+    /// Prints the log to stdout in the chosen format instead of showing it, until interrupted
+    pub async fn print(&mut self) -> Result<(), Error> {
+        let serial = resolve_serial(self.use_default_serial)
+            .await?
+            .ok_or(Error::NoDevice)?;
+
+        let messages = until_ctrl_c(adb::logcat(
+            adb::transport(),
+            &serial,
+            &self.logcat_options(),
+        ));
+        pin!(messages);
+        let mut stdout = std::io::stdout().lock();
+        while let Some(message) = messages.next().await {
+            stdout.write_all(&format_message_bytes(&message?, self.args.format))?;
+            stdout.write_all(b"\n")?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    // names the process that logged the selected message, for a toast. unless it's been looked up
+    // before, that's left to a background `ps` and only a placeholder is returned
+    fn describe_selected_process(&mut self, serial: &str) -> Option<String> {
+        let pid = self.focused_ref().selected_message()?.pid;
+        if let Some(name) = self.process_names.get(&(serial.to_string(), pid)) {
+            return Some(format!("pid {pid}: {name}"));
+        }
+
+        let serial = serial.to_string();
+        let lookups = self.lookups.clone();
+        tokio::spawn(async move {
+            let name = adb::process_name(adb::transport(), &serial, pid).await;
+            let _ = lookups.send(ProcessLookup { serial, pid, name });
+        });
+        Some(format!("looking up pid {pid}…"))
+    }
+
+    // remembers what a background `ps` found, describing it for a toast
+    fn describe_process(&mut self, lookup: ProcessLookup) -> String {
+        let ProcessLookup { serial, pid, name } = lookup;
+        match name {
+            Ok(Some(name)) => {
+                let message = format!("pid {pid}: {name}");
+                self.process_names.insert((serial, pid), name);
+                message
+            }
+            Ok(None) => format!("pid {pid} isn't running anymore"),
+            Err(err) => format!("couldn't look up pid {pid}: {err}"),
+        }
+    }
+
+    fn jump_level(&self) -> LogLevel {
+        self.args.jump_level.unwrap_or(LogLevel::Warning)
+    }
+
+    fn logcat_options(&self) -> LogcatOptions {
+        LogcatOptions {
+            text_only: self.args.text_only.unwrap_or_default(),
+            min_level: self.args.min_level,
+            pids: self.args.pids.clone(),
+            // already checked when parsing the arguments
+            filterspecs: self
+                .args
+                .filterspec
+                .as_deref()
+                .map(|spec| shell_words::split(spec).unwrap())
+                .unwrap_or_default(),
+            dividers: self.args.dividers.unwrap_or_default(),
+            strip_ansi: !self.args.keep_ansi.unwrap_or_default(),
+            // only the raw format writes them out
+            keep_raw: matches!(self.args.format, LogFormat::Raw),
+            read_buffer_size: self.args.read_buffer_size,
+            ..Default::default()
+        }
+    }
+
+    // `focused` marks the pane keys act on when the log is split
+    fn log_widget(&self, name: &str, following: bool, focused: bool) -> Log<'static> {
+        let log = Log::new()
+            .color_by(self.args.color_by.unwrap_or_default())
+            .time_format(self.args.time_format.unwrap_or_default())
+            .compact(self.compact)
+            .multiline(self.multiline)
+            .show_pid(self.show_pid)
+            .show_tid(self.show_tid)
+            .line_numbers(self.line_numbers)
+            .source_links(self.editor_url.is_some());
+        if self.zoom {
+            return log;
+        }
+
+        let mode = if following { "follow" } else { "paused" };
+        let title = if self.sync_scroll {
+            format!("{name} [{mode}, synced]")
+        } else {
+            format!("{name} [{mode}]")
+        };
+        let border_type = if focused {
+            BorderType::Thick
+        } else {
+            BorderType::Plain
+        };
+        log.block(
+            Block::default()
+                .title(title)
+                .title_alignment(tui::layout::Alignment::Left)
+                .borders(Borders::all())
+                .border_type(border_type),
+        )
+    }
+
     fn ui<B: Backend>(&mut self, f: &mut Frame<B>) {
         self.fps_overlay.record_new_frame();
 
@@ -185,24 +756,56 @@ impl LogcatApp {
             .constraints([Constraint::Min(10), Constraint::Length(1)])
             .split(f.size());
 
-        let mut log = Log::new();
-        if !self.zoom {
-            log = log.block(
-                Block::default()
-                    .title("Log")
-                    .title_alignment(tui::layout::Alignment::Left)
-                    .borders(Borders::all()),
-            );
+        let log_following = self.log.as_ref().unwrap().is_following();
+        if let [left, right] = self.args.split[..] {
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(chunks[0]);
+
+            let name = |lid| log_id_name(lid).unwrap_or("unknown");
+            let split_following = self.split.as_ref().unwrap().is_following();
+            let left = self.log_widget(name(left), log_following, !self.focus_split);
+            let right = self.log_widget(name(right), split_following, self.focus_split);
+            f.render_stateful_widget(left, panes[0], self.log.as_mut().unwrap());
+            f.render_stateful_widget(right, panes[1], self.split.as_mut().unwrap());
+        } else {
+            let log = self.log_widget("Log", log_following, false);
+            f.render_stateful_widget(log, chunks[0], self.log.as_mut().unwrap());
+        }
+
+        if let Some((prompt, input)) = &self.prompt {
+            let prompt = Paragraph::new(format!("{}{input}", prompt.label()));
+            f.render_widget(prompt, chunks[1]);
+        } else if let Some((message, _)) = &self.toast {
+            f.render_widget(Paragraph::new(message.as_str()), chunks[1]);
+        } else {
+            let log = self.focused_ref();
+            let status_bar = StatusBar::new()
+                .rate(log.rate())
+                .dropped(log.dropped())
+                .decode_errors(log.decode_errors());
+            f.render_stateful_widget(status_bar, chunks[1], self.status_bar.as_mut().unwrap());
         }
-        f.render_stateful_widget(log, chunks[0], self.log.as_mut().unwrap());
 
-        let status_bar = StatusBar::new();
-        f.render_stateful_widget(status_bar, chunks[1], &mut self.status_bar);
+        if let Some(shell) = &mut self.shell {
+            f.render_stateful_widget(ShellOverlay::new(), f.size(), shell);
+        }
 
         if self.debug {
             // render overlay last so it can pop over everything else
             let fps_overlay = FpsOverlay::new();
             f.render_stateful_widget(fps_overlay, f.size(), &mut self.fps_overlay);
         }
+
+        theme::strip_colors(f);
+    }
+}
+
+// waits for the right-hand pane's next message, if the log is split
+async fn poll_split(split: &mut Option<LogState>) {
+    match split {
+        Some(split) => split.poll().await,
+        None => futures::future::pending().await,
     }
 }
@@ -0,0 +1,35 @@
+use std::{fs::File, sync::Mutex};
+
+use tracing::Level;
+
+use crate::{cache::xadb_dir, theme::colors_enabled};
+
+fn log_location() -> std::path::PathBuf {
+    xadb_dir().join("xadb.log")
+}
+
+/// Initializes `tracing` output for `--debug`.
+///
+/// TUI commands own stderr for the alternate screen, so in that case output goes to
+/// `$XADB_DIR/xadb.log` instead.
+pub fn init(is_tui: bool) -> std::io::Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .with_target(false);
+
+    if is_tui {
+        std::fs::create_dir_all(xadb_dir())?;
+        let file = File::create(log_location())?;
+        builder
+            .with_ansi(false)
+            .with_writer(Mutex::new(file))
+            .init();
+    } else {
+        builder
+            .with_ansi(colors_enabled())
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    Ok(())
+}
@@ -1,24 +1,34 @@
 use std::{
     env::VarError,
     error::Error,
-    io::{self, Stderr},
+    io::{self, Stderr, Write},
+    process::ExitCode,
     time::Duration,
 };
 
 use cache::Cache;
 use clap::Parser;
-use cli::{Args, Command};
-use commands::adb::track_devices;
+use cli::{Args, Command, LogcatArgs};
+use commands::adb::{self, track_devices};
+use config::Config;
 use crossterm::{
     event::{DisableMouseCapture, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use device_select::DeviceSelectApp;
-use devices::query_devices_continuously;
-use futures::StreamExt;
-use tui::{backend::CrosstermBackend, Terminal};
+use device_select::{DeviceSelectApp, DeviceSelectOptions, SelectOutcome};
+use devices::{device_events, online_devices, query_devices_continuously};
+use futures::{Stream, StreamExt};
+use inline::InlineBackend;
+use interrupt::until_ctrl_c;
+use logcat::LogcatOutcome;
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    terminal::{TerminalOptions, Viewport},
+    Terminal,
+};
 
+mod anonymize;
 mod battery;
 mod cache;
 mod commands {
@@ -26,94 +36,270 @@ mod commands {
     pub(crate) mod fastboot;
 }
 mod cli;
+mod config;
 mod device_select;
 mod devices;
+mod dumpsys;
+mod emulator;
+mod events;
+mod export;
+mod hooks;
+mod hyperlink;
 mod init_shell;
+mod inline;
+mod interrupt;
+mod line_select;
 mod logcat;
+mod logging;
+mod serial;
+mod theme;
 mod widgets;
 
-async fn build_and_run_app(
-    terminal: &mut Terminal<CrosstermBackend<Stderr>>,
-) -> Result<Option<String>, Box<dyn Error>> {
+async fn build_and_run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    options: &DeviceSelectOptions,
+) -> Result<SelectOutcome, Box<dyn Error>> {
     // create app and run it
     let tick_rate = Duration::from_millis(250);
-    let mut app = DeviceSelectApp::load_initial_state().await?;
+    let mut app = DeviceSelectApp::load_initial_state(options).await?;
     Ok(app.run(terminal, tick_rate).await?)
 }
 
+// shows the log of a device picked in the selector, with the options `xadb logcat` would have
+async fn view_logs(
+    terminal: &mut Terminal<CrosstermBackend<Stderr>>,
+    config: &Config,
+    serial: String,
+    use_default_serial: bool,
+    select_options: DeviceSelectOptions,
+) -> Result<LogcatOutcome, Box<dyn Error>> {
+    let mut args = LogcatArgs::from_env();
+    config.apply_logcat(&mut args);
+
+    let mut app = logcat::LogcatApp::new(args, use_default_serial, select_options);
+    Ok(app.view(terminal, serial).await?)
+}
+
+// losing the device is reported as an error, so scripts can tell it apart from quitting
+fn disconnected_error(outcome: LogcatOutcome) -> Result<(), Box<dyn Error>> {
+    match outcome {
+        LogcatOutcome::Disconnected(serial) => Err(format!("lost the log of {serial}").into()),
+        LogcatOutcome::Quit | LogcatOutcome::Cancelled => Ok(()),
+    }
+}
+
+// for terminals that can't be taken over, like over ssh without a tty
+async fn select_without_tui(err: Box<dyn Error>, no_cache: bool) -> Result<(), Box<dyn Error>> {
+    tracing::debug!("couldn't set up the terminal, falling back to a numbered list: {err}");
+    // raw mode may have been entered before whatever failed
+    let _ignored = disable_raw_mode();
+
+    if let Some(serial) = line_select::select_by_number(no_cache).await? {
+        println!("{serial}");
+    }
+    Ok(())
+}
+
 fn is_tui(args: &Args) -> bool {
-    match args.command {
-        Command::List | Command::Logcat => true,
+    match &args.command {
+        Command::List { .. } | Command::Dumpsys { raw: false, .. } => true,
+        Command::Logcat(logcat_args) => !logcat_args.print,
         _ => false,
     }
 }
 
-struct TuiConfiguration {
-    terminal: Terminal<CrosstermBackend<Stderr>>,
+// how many lines the selector takes up with `list --inline`
+const INLINE_HEIGHT: u16 = 12;
+
+struct TuiConfiguration<B: Backend + Write = CrosstermBackend<Stderr>> {
+    terminal: Terminal<B>,
+    inline: bool,
 }
 
 impl TuiConfiguration {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        enable_raw_mode()?;
+        let mut stderr = io::stderr();
+        execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
+        let backend = CrosstermBackend::new(stderr);
+        Ok(TuiConfiguration {
+            terminal: Terminal::new(backend)?,
+            inline: false,
+        })
+    }
+}
+
+impl TuiConfiguration<InlineBackend> {
+    /// Draws in the `height` lines below the cursor instead of taking over the screen
+    fn inline(height: u16) -> Result<Self, Box<dyn Error>> {
+        let backend = InlineBackend::below_cursor(height)?;
+        let viewport = Viewport::fixed(backend.viewport()?);
+        enable_raw_mode()?;
+        Ok(TuiConfiguration {
+            terminal: Terminal::with_options(backend, TerminalOptions { viewport })?,
+            inline: true,
+        })
+    }
+}
+
+impl<B: Backend + Write> TuiConfiguration<B> {
     fn try_drop(&mut self) -> Result<(), Box<dyn Error>> {
         // restore terminal
         disable_raw_mode()?;
-        execute!(
-            self.terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
-        )?;
+        if self.inline {
+            // leaves the cursor where the list was, for whatever is printed next
+            self.terminal.clear()?;
+        } else {
+            execute!(
+                self.terminal.backend_mut(),
+                LeaveAlternateScreen,
+                DisableMouseCapture
+            )?;
+        }
         self.terminal.show_cursor()?;
         Ok(())
     }
 }
 
-impl Drop for TuiConfiguration {
+impl<B: Backend + Write> Drop for TuiConfiguration<B> {
     fn drop(&mut self) {
         let _ignored = self.try_drop();
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
+async fn run(mut args: Args) -> Result<(), Box<dyn Error>> {
+    let config = Config::load().await?;
+    config.apply(&mut args);
 
-    // for TUI commands, set up terminal
-    let mut maybe_terminal = if is_tui(&args) {
-        enable_raw_mode()?;
-        let mut stderr = io::stderr();
-        execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
-        let backend = CrosstermBackend::new(stderr);
-        Some(TuiConfiguration {
-            terminal: Terminal::new(backend)?,
-        })
-    } else {
-        None
+    let no_cache = args.no_cache.unwrap_or_default();
+    // the last selected device lives in the cache
+    let use_default_serial = !args.no_default.unwrap_or_default() && !no_cache;
+    let mut select_options = DeviceSelectOptions {
+        no_cache,
+        ..Default::default()
     };
+    if !args.columns.is_empty() {
+        select_options.columns = args.columns.clone();
+    }
+
+    theme::set_color_choice(args.color, is_tui(&args));
+    if let Some(selection) = &config.theme.selection {
+        theme::set_selection_style(selection);
+    }
+
+    if args.debug {
+        logging::init(is_tui(&args))?;
+    }
+
+    anonymize::set_enabled(args.anonymize);
+    cache::set_cache_key(args.cache_key.unwrap_or_default());
+    devices::set_skipped_sources(args.no_adb, args.no_fastboot);
+    if let Some(secs) = args.battery_interval {
+        // the config file isn't range checked like the flag is
+        battery::set_battery_interval(Duration::from_secs(secs.max(1)));
+    }
+
+    if args.dry_run {
+        // the printed commands would be drawn over by the TUI
+        if is_tui(&args) {
+            return Err("--dry-run can't be used with interactive commands".into());
+        }
+        adb::set_dry_run(true);
+    }
 
     match args.command {
-        Command::List => {
-            let terminal = maybe_terminal.as_mut().unwrap();
+        Command::List { once, inline } => {
+            if once {
+                // an offline or unauthorized device can't be used, so it's left to the selector
+                let usable: Vec<_> = online_devices()
+                    .await
+                    .into_iter()
+                    .filter(|d| d.properties.connection_state == "device")
+                    .collect();
+                if let [device] = &usable[..] {
+                    let mut cache = Cache::load(no_cache).await?;
+                    cache.last_selected = Some(device.connection_name.clone());
+                    cache.persist().await?;
 
-            let res = build_and_run_app(&mut terminal.terminal).await;
+                    println!("{}", device.connection_name);
+                    return Ok(());
+                }
+            }
 
-            // drop terminal before printing output
-            std::mem::drop(maybe_terminal);
+            // the terminal is dropped at the end of each branch, before printing output
+            let res = if inline {
+                let mut terminal = match TuiConfiguration::inline(INLINE_HEIGHT) {
+                    Ok(terminal) => terminal,
+                    Err(err) => return select_without_tui(err, no_cache).await,
+                };
+                build_and_run_app(&mut terminal.terminal, &select_options).await
+            } else {
+                let mut terminal = match TuiConfiguration::new() {
+                    Ok(terminal) => terminal,
+                    Err(err) => return select_without_tui(err, no_cache).await,
+                };
+                loop {
+                    match build_and_run_app(&mut terminal.terminal, &select_options).await {
+                        // stay on the alternate screen rather than flashing back to the shell
+                        Ok(SelectOutcome::ViewLogs(serial)) => {
+                            let outcome = view_logs(
+                                &mut terminal.terminal,
+                                &config,
+                                serial,
+                                use_default_serial,
+                                select_options.clone(),
+                            )
+                            .await?;
+                            // back to the list to pick another device
+                            if let LogcatOutcome::Disconnected(_) = outcome {
+                                continue;
+                            }
+                            return Ok(());
+                        }
+                        res => break res,
+                    }
+                }
+            };
 
-            match res {
-                Ok(Some(serial)) => {
-                    println!("{serial}");
+            match res? {
+                // stdout is captured by the shell integration as the serial, so errors must only
+                // go to stderr
+                SelectOutcome::Selected(serial) => println!("{serial}"),
+                // the inline list is too small to show the log in
+                SelectOutcome::ViewLogs(serial) => {
+                    let mut terminal = TuiConfiguration::new()?;
+                    let outcome = view_logs(
+                        &mut terminal.terminal,
+                        &config,
+                        serial,
+                        use_default_serial,
+                        select_options,
+                    )
+                    .await?;
+                    return disconnected_error(outcome);
                 }
-                Ok(None) => {}
-                Err(err) => println!("{err:?}"),
+                SelectOutcome::Cancelled => {}
             }
 
             Ok(())
         }
-        Command::ClearCache => {
-            let _ = Cache::clear().await;
+        Command::ClearCache { serial: None } => match Cache::clear().await {
+            // nothing to clear
+            Err(cache::Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        },
+        Command::ClearCache {
+            serial: Some(serial),
+        } => {
+            let mut cache = Cache::load_from_disk().await?;
+            if !cache.remove_device(&serial) {
+                return Err(format!("{serial} isn't in the cache").into());
+            }
+            cache.persist().await?;
             Ok(())
         }
         Command::CurrentProduct => {
-            let cache = Cache::load_from_disk().await?;
+            let cache = Cache::load(no_cache).await?;
 
             let serial = match std::env::var("ANDROID_SERIAL") {
                 Ok(serial) => serial,
@@ -135,13 +321,45 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
             Ok(())
         }
+        Command::Prompt => {
+            let serial = match std::env::var("ANDROID_SERIAL") {
+                Ok(serial) if !serial.is_empty() => serial,
+                _ => return Ok(()),
+            };
+
+            let cache = Cache::load(no_cache).await?;
+            let product = cache
+                .devices
+                .get(&serial)
+                .and_then(|device| device.live.as_ref())
+                .map_or(serial.as_str(), |live| live.product.as_str());
+
+            // without a running server there's nothing cheap to check against
+            let marker = match adb::server_devices(adb::transport()).await {
+                Ok(devices) => {
+                    let connected = devices.iter().any(|d| d.connection_name == serial);
+                    match (connected, devices.len()) {
+                        (false, _) => "!".to_string(),
+                        (true, 1) => "*".to_string(),
+                        (true, n) => format!("+{}", n - 1),
+                    }
+                }
+                Err(err) => {
+                    tracing::debug!("couldn't reach adb server: {err:?}");
+                    String::new()
+                }
+            };
+
+            println!("{product}{marker}");
+            Ok(())
+        }
         Command::InitShell { shell } => Ok(init_shell::init_shell(&shell)?),
         Command::Select => match std::env::var("XADB_INIT_SHELL") {
             Ok(shell) => {
                 match shell.as_str() {
                     "bash" | "zsh" => (),
                     _ => {
-                        panic!("Shell {shell} not supported");
+                        return Err(format!("Shell {shell} not supported").into());
                     }
                 }
 
@@ -170,31 +388,242 @@ eval "$(xadb init-shell bash)"
                 std::process::exit(1);
             }
         },
-        Command::Battery => {
-            let level = battery::battery().await?;
-            println!("{level}");
+        Command::Which => {
+            let serial = serial::target_serial(use_default_serial).await?;
+            println!("{}", anonymize::display_serial(&serial));
+            Ok(())
+        }
+        Command::Use { device } => {
+            if no_cache {
+                return Err(
+                    "the default device is remembered in the cache, which --no-cache \
+                            disables"
+                        .into(),
+                );
+            }
+            let serial = serial::use_device(&device).await?;
+            eprintln!("using {}", anonymize::display_serial(&serial));
+            Ok(())
+        }
+        Command::Devices { csv } => {
+            let cache = Cache::load(no_cache).await?;
+            export::print_devices(&online_devices().await, &cache, csv)?;
+            Ok(())
+        }
+        Command::Pair {
+            addr,
+            code,
+            connect,
+        } => {
+            let code = match code {
+                Some(code) => code,
+                None => {
+                    eprint!("pairing code: ");
+                    io::stderr().flush()?;
+                    // nothing else is running, so blocking on stdin is fine
+                    let mut code = String::new();
+                    io::stdin().read_line(&mut code)?;
+                    code.trim().to_string()
+                }
+            };
+
+            // nothing is run in a dry run, so there's no response to check
+            if args.dry_run {
+                adb::pair(adb::transport(), &addr, &code).await.ok();
+                if let Some(connect) = connect {
+                    adb::connect(adb::transport(), &connect).await.ok();
+                }
+                return Ok(());
+            }
+
+            println!("{}", adb::pair(adb::transport(), &addr, &code).await?);
+            if let Some(connect) = connect {
+                println!("{}", adb::connect(adb::transport(), &connect).await?);
+            }
+            Ok(())
+        }
+        Command::Forward {
+            local,
+            remote,
+            list,
+        } => {
+            let serial = serial::resolve_serial(use_default_serial).await?;
+            if list {
+                // adb lists every device's forwards, whichever is picked
+                for forward in adb::forward_list(adb::transport(), serial.as_deref()).await? {
+                    if serial
+                        .as_ref()
+                        .is_none_or(|serial| *serial == forward.serial)
+                    {
+                        println!("{}\t{forward}", anonymize::display_serial(&forward.serial));
+                    }
+                }
+                return Ok(());
+            }
+
+            serial::ensure_booted(serial.as_deref()).await?;
+            // both are required without --list
+            let (local, remote) = (local.unwrap(), remote.unwrap());
+            if let Some(port) =
+                adb::forward(adb::transport(), serial.as_deref(), &local, &remote).await?
+            {
+                println!("{port}");
+            }
+            Ok(())
+        }
+        Command::Reverse {
+            remote,
+            local,
+            list,
+        } => {
+            let serial = serial::resolve_serial(use_default_serial).await?;
+            if list {
+                for reverse in adb::reverse_list(adb::transport(), serial.as_deref()).await? {
+                    println!("{reverse}");
+                }
+                return Ok(());
+            }
+
+            serial::ensure_booted(serial.as_deref()).await?;
+            // both are required without --list
+            let (remote, local) = (remote.unwrap(), local.unwrap());
+            if let Some(port) =
+                adb::reverse(adb::transport(), serial.as_deref(), &remote, &local).await?
+            {
+                println!("{port}");
+            }
+            Ok(())
+        }
+        Command::Battery { all: true } => {
+            let levels = battery::battery_all().await;
+
+            // nothing was run, so there are no levels to report
+            if args.dry_run {
+                return Ok(());
+            }
+
+            for (serial, level) in levels {
+                let serial = anonymize::display_serial(&serial);
+                match level {
+                    Ok(level) => println!("{serial}: {level}%"),
+                    Err(err) => println!("{serial}: {err}"),
+                }
+            }
             Ok(())
         }
-        Command::Logcat => {
-            let terminal = maybe_terminal.as_mut().unwrap();
+        Command::Battery { all: false } => {
+            let serial = serial::resolve_serial(use_default_serial).await?;
+            serial::ensure_booted(serial.as_deref()).await?;
+            let level = battery::battery(adb::transport(), serial.as_deref()).await;
+
+            // nothing was run, so there's no level to report
+            if args.dry_run {
+                return Ok(());
+            }
 
-            let mut app = logcat::LogcatApp::new();
-            app.run(&mut terminal.terminal).await?;
+            println!("{}", level?);
             Ok(())
         }
-        Command::TrackDevices => {
-            let mut devices = Box::pin(track_devices());
-            while let Some(device) = devices.next().await {
-                println!("{device:?}");
+        Command::Sideload { package } => {
+            let serial = serial::resolve_serial(use_default_serial).await?;
+            adb::sideload(adb::transport(), serial.as_deref(), &package).await?;
+            Ok(())
+        }
+        Command::Dumpsys { service, args, raw } => {
+            let serial = serial::resolve_serial(use_default_serial).await?;
+            let command = dumpsys::dumpsys_command(&service, &args);
+
+            if raw {
+                let output = adb::shell(adb::transport(), serial.as_deref(), &command);
+                let mut output = Box::pin(until_ctrl_c(output));
+                while let Some(line) = output.next().await {
+                    println!("{}", line?);
+                }
+                return Ok(());
             }
+
+            let mut terminal = TuiConfiguration::new()?;
+            dumpsys::DumpsysApp::new(serial.as_deref(), command)
+                .run(&mut terminal.terminal)
+                .await?;
+            Ok(())
+        }
+        Command::Logcat(logcat_args) if logcat_args.print => {
+            let mut app = logcat::LogcatApp::new(logcat_args, use_default_serial, select_options);
+            app.print().await?;
             Ok(())
         }
-        Command::AllDevices => {
-            let mut devices = Box::pin(query_devices_continuously(Duration::from_secs(10)));
-            while let Some(device) = devices.next().await {
-                println!("{device:?}");
+        Command::Logcat(logcat_args) => {
+            let mut terminal = TuiConfiguration::new()?;
+
+            let mut app = logcat::LogcatApp::new(logcat_args, use_default_serial, select_options);
+            let outcome = app.run(&mut terminal.terminal).await?;
+            disconnected_error(outcome)
+        }
+        Command::TrackDevices { max_events } => {
+            let mut devices = Box::pin(until_ctrl_c(at_most(
+                track_devices(adb::transport()),
+                max_events,
+            )));
+            while let Some(devices) = devices.next().await {
+                let devices = devices.map(|devices| {
+                    devices
+                        .into_iter()
+                        .map(|device| device.map(anonymize::display_device))
+                        .collect::<Vec<_>>()
+                });
+                println!("{devices:?}");
             }
             Ok(())
         }
+        Command::Watch { max_events } => {
+            let devices = query_devices_continuously(Duration::from_secs(2));
+            let mut events = Box::pin(until_ctrl_c(at_most(device_events(devices), max_events)));
+            while let Some(event) = events.next().await {
+                if let Some(command) = &config.on_connect {
+                    hooks::on_device_event(command, &event);
+                }
+                println!("{}", anonymize::display_event(event));
+            }
+            Ok(())
+        }
+        Command::AllDevices { max_events } => {
+            let mut devices = Box::pin(until_ctrl_c(at_most(
+                query_devices_continuously(Duration::from_secs(10)),
+                max_events,
+            )));
+            while let Some(devices) = devices.next().await {
+                let devices: Vec<_> = devices.into_iter().map(anonymize::display_device).collect();
+                println!("{devices:?}");
+            }
+            Ok(())
+        }
+    }
+}
+
+// ends `stream` after `max` items, if given, for scripts that want a bounded run
+fn at_most<S: Stream>(stream: S, max: Option<usize>) -> impl Stream<Item = S::Item> {
+    stream.take(max.unwrap_or(usize::MAX))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let result = run(args).await;
+    // don't leave adb or fastboot running after xadb is gone
+    adb::kill_children();
+
+    match result {
+        // the conventional status for SIGINT, so scripts can tell the output is partial
+        Ok(()) if interrupt::was_interrupted() => {
+            let _ignored = io::stdout().flush();
+            ExitCode::from(130)
+        }
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,168 @@
+use quick_error::quick_error;
+
+use crate::{
+    cache::Cache,
+    commands::adb,
+    devices::{not_booted_hint, online_devices, AdbDevice},
+};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Cache(err: crate::cache::Error) {
+            from()
+        }
+        NotFound(name: String, connected: Vec<String>) {
+            display("{} not found; connected: {}", name, connected.join(", "))
+        }
+        Ambiguous(name: String, matches: Vec<String>) {
+            display("{} matches multiple devices: {}", name, matches.join(", "))
+        }
+        NoDevices {
+            display("no devices connected")
+        }
+        NotBooted(serial: String, state: String, hint: &'static str) {
+            display("{} is in {}; {}", serial, state, hint)
+        }
+        MultipleDevices(connected: Vec<String>) {
+            display("multiple devices connected: {}; set ANDROID_SERIAL or run `xadb use`",
+                connected.join(", "))
+        }
+    }
+}
+
+/// Finds the device `name` refers to: a serial, a transport id, or a product, model, or device
+/// name that only one connected device has. `label` is how `name` is described in errors.
+fn find_device<'a>(
+    devices: &'a [AdbDevice],
+    name: &str,
+    label: &str,
+) -> Result<&'a AdbDevice, Error> {
+    if let Some(device) = devices.iter().find(|d| d.connection_name == name) {
+        return Ok(device);
+    }
+
+    let matches: Vec<&AdbDevice> = devices
+        .iter()
+        .filter(|d| match &d.properties.live {
+            Some(live) => {
+                live.transport_id.to_string() == name
+                    || live.product == name
+                    || live.model == name
+                    || live.device == name
+            }
+            None => false,
+        })
+        .collect();
+
+    match matches[..] {
+        [device] => Ok(device),
+        [] => Err(Error::NotFound(
+            label.to_string(),
+            devices.iter().map(|d| d.connection_name.clone()).collect(),
+        )),
+        _ => Err(Error::Ambiguous(
+            label.to_string(),
+            matches.iter().map(|d| d.connection_name.clone()).collect(),
+        )),
+    }
+}
+
+/// Picks the serial a command should target: the device `ANDROID_SERIAL` refers to if it's set,
+/// otherwise the device last picked in the selector when `use_default` is true and it's still
+/// connected.
+///
+/// Returns `None` when neither is available, leaving it to adb to pick a device.
+pub async fn resolve_serial(use_default: bool) -> Result<Option<String>, Error> {
+    match std::env::var("ANDROID_SERIAL") {
+        // nothing is queried in a dry run, so there are no devices to check against
+        Ok(serial) if !serial.is_empty() && adb::is_dry_run() => return Ok(Some(serial)),
+        Ok(serial) if !serial.is_empty() => {
+            let devices = online_devices().await;
+            return Ok(Some(
+                find_device(&devices, &serial, &format!("ANDROID_SERIAL={serial}"))?
+                    .connection_name
+                    .clone(),
+            ));
+        }
+        _ => {}
+    }
+
+    if !use_default {
+        return Ok(None);
+    }
+
+    let Some(serial) = Cache::load_from_disk().await?.last_selected else {
+        return Ok(None);
+    };
+
+    let devices = online_devices().await;
+    Ok(devices
+        .iter()
+        .any(|d| d.connection_name == serial)
+        .then_some(serial))
+}
+
+/// The serial of the device a command would target: the one `resolve_serial` picks, or else the
+/// only device adb can talk to, as adb itself would choose.
+pub async fn target_serial(use_default: bool) -> Result<String, Error> {
+    if let Some(serial) = resolve_serial(use_default).await? {
+        return Ok(serial);
+    }
+
+    // adb only picks a device on its own if it's the only one that's fully connected
+    let connected: Vec<String> = online_devices()
+        .await
+        .into_iter()
+        .filter(|d| d.properties.connection_state == "device")
+        .map(|d| d.connection_name)
+        .collect();
+    match &connected[..] {
+        [serial] => Ok(serial.clone()),
+        [] => Err(Error::NoDevices),
+        _ => Err(Error::MultipleDevices(connected)),
+    }
+}
+
+/// Remembers the device `name` refers to as the default for later commands, like picking it in the
+/// selector does. Returns its serial.
+pub async fn use_device(name: &str) -> Result<String, Error> {
+    let devices = online_devices().await;
+    let serial = find_device(&devices, name, name)?.connection_name.clone();
+
+    let mut cache = Cache::load_from_disk().await?;
+    cache.last_selected = Some(serial.clone());
+    cache.persist().await?;
+    Ok(serial)
+}
+
+/// Fails if the device `serial` refers to, or the only connected device when it's `None`, is in a
+/// state like recovery where Android isn't running to answer for its logs or battery
+pub async fn ensure_booted(serial: Option<&str>) -> Result<(), Error> {
+    // nothing is queried in a dry run
+    if adb::is_dry_run() {
+        return Ok(());
+    }
+
+    let devices = online_devices().await;
+    let device = match serial {
+        Some(serial) => devices.iter().find(|d| d.connection_name == serial),
+        None => match &devices[..] {
+            [device] => Some(device),
+            _ => None,
+        },
+    };
+    let Some(device) = device else {
+        return Ok(());
+    };
+
+    let state = &device.properties.connection_state;
+    match not_booted_hint(state) {
+        Some(hint) => Err(Error::NotBooted(
+            device.connection_name.clone(),
+            state.clone(),
+            hint,
+        )),
+        None => Ok(()),
+    }
+}
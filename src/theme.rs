@@ -0,0 +1,252 @@
+use std::{
+    io::IsTerminal,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use serde::Deserialize;
+
+use tui::{
+    backend::Backend,
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::Widget,
+    Frame,
+};
+
+use crate::commands::adb::LogLevel;
+
+// colors are kept here rather than with any one widget so that every view of the log agrees
+
+static COLORS_ENABLED: AtomicBool = AtomicBool::new(true);
+static SELECTION_STYLE: OnceLock<Style> = OnceLock::new();
+
+/// When to use color, following the `--color` convention of other tools
+#[derive(Debug, Default, Copy, Clone, clap::ValueEnum)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    /// Only when writing to a terminal and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+}
+
+/// Decides once whether output is colored. `to_stderr` is where the output goes: the TUI draws to
+/// stderr, while plain output goes to stdout, which is often captured by the shell.
+pub fn set_color_choice(choice: ColorChoice, to_stderr: bool) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            // any value disables color, see https://no-color.org
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            let is_terminal = if to_stderr {
+                std::io::stderr().is_terminal()
+            } else {
+                std::io::stdout().is_terminal()
+            };
+            !no_color && is_terminal
+        }
+    };
+    COLORS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn colors_enabled() -> bool {
+    COLORS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Removes the colors from everything drawn to the frame so far if color is disabled. Call it last
+/// when drawing.
+pub fn strip_colors<B: Backend>(f: &mut Frame<B>) {
+    if !colors_enabled() {
+        f.render_widget(Monochrome, f.size());
+    }
+}
+
+// resets every cell's colors, reversing the ones that had a background so highlights, headers and
+// severe messages still stand out
+struct Monochrome;
+
+impl Widget for Monochrome {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                if cell.bg != Color::Reset {
+                    cell.modifier.toggle(Modifier::REVERSED);
+                }
+                cell.fg = Color::Reset;
+                cell.bg = Color::Reset;
+            }
+        }
+    }
+}
+
+/// A color in the config file: a name like `gray` or `light-blue`, an xterm color number like
+/// `"208"`, or `#rrggbb`
+#[derive(Debug, Copy, Clone)]
+pub struct ThemeColor(Color);
+
+impl std::str::FromStr for ThemeColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let color = match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "reset" => Color::Reset,
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "gray" | "grey" => Color::Gray,
+            "dark-gray" | "dark-grey" => Color::DarkGray,
+            "light-red" => Color::LightRed,
+            "light-green" => Color::LightGreen,
+            "light-yellow" => Color::LightYellow,
+            "light-blue" => Color::LightBlue,
+            "light-magenta" => Color::LightMagenta,
+            "light-cyan" => Color::LightCyan,
+            "white" => Color::White,
+            hex if hex.len() == 7 && hex.starts_with('#') => {
+                let rgb = u32::from_str_radix(&hex[1..], 16)
+                    .map_err(|_| format!("invalid color '{s}'"))?;
+                Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+            }
+            index => Color::Indexed(index.parse().map_err(|_| {
+                format!("unknown color '{s}', expected a name, a number up to 255, or #rrggbb")
+            })?),
+        };
+        Ok(ThemeColor(color))
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeModifier {
+    Bold,
+    Dim,
+    Italic,
+    Underlined,
+    SlowBlink,
+    RapidBlink,
+    Reversed,
+    Hidden,
+    CrossedOut,
+}
+
+impl From<ThemeModifier> for Modifier {
+    fn from(modifier: ThemeModifier) -> Self {
+        match modifier {
+            ThemeModifier::Bold => Modifier::BOLD,
+            ThemeModifier::Dim => Modifier::DIM,
+            ThemeModifier::Italic => Modifier::ITALIC,
+            ThemeModifier::Underlined => Modifier::UNDERLINED,
+            ThemeModifier::SlowBlink => Modifier::SLOW_BLINK,
+            ThemeModifier::RapidBlink => Modifier::RAPID_BLINK,
+            ThemeModifier::Reversed => Modifier::REVERSED,
+            ThemeModifier::Hidden => Modifier::HIDDEN,
+            ThemeModifier::CrossedOut => Modifier::CROSSED_OUT,
+        }
+    }
+}
+
+/// A style in the config file, like `{ fg = "white", bg = "blue", modifiers = ["bold"] }`. Anything
+/// left out keeps the style's default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    pub fg: Option<ThemeColor>,
+    pub bg: Option<ThemeColor>,
+    pub modifiers: Option<Vec<ThemeModifier>>,
+}
+
+impl StyleConfig {
+    /// `default` with whatever this sets replacing its colors and modifiers
+    pub fn over(&self, default: Style) -> Style {
+        let mut style = default;
+        if let Some(ThemeColor(fg)) = self.fg {
+            style.fg = Some(fg);
+        }
+        if let Some(ThemeColor(bg)) = self.bg {
+            style.bg = Some(bg);
+        }
+        if let Some(modifiers) = &self.modifiers {
+            style.add_modifier = modifiers
+                .iter()
+                .fold(Modifier::empty(), |all, modifier| all | (*modifier).into());
+            style.sub_modifier = Modifier::empty();
+        }
+        style
+    }
+}
+
+fn default_selection_style() -> Style {
+    Style::default()
+        .fg(Color::Black)
+        .bg(Color::Gray)
+        .add_modifier(Modifier::BOLD)
+}
+
+/// Uses the `[theme]` table's `selection` style in place of the default. Only the first call has
+/// any effect.
+pub fn set_selection_style(config: &StyleConfig) {
+    let _ignored = SELECTION_STYLE.set(config.over(default_selection_style()));
+}
+
+/// How the selected device or log message is highlighted
+pub fn selection_style() -> Style {
+    *SELECTION_STYLE.get_or_init(default_selection_style)
+}
+
+/// The color a device's adb or fastboot connection state is shown in
+pub fn connection_state_color(state: &str) -> Color {
+    match state {
+        "device" => Color::Green,
+        "fastboot" => Color::Yellow,
+        "recovery" => Color::Magenta,
+        "sideload" => Color::LightBlue,
+        _ => Color::Cyan,
+    }
+}
+
+/// The background that marks a message as needing attention, for warnings and above
+pub fn level_bg_color(level: LogLevel) -> Option<Color> {
+    match level {
+        LogLevel::Fatal => Some(Color::Red),
+        LogLevel::Error => Some(Color::LightRed),
+        LogLevel::Warning => Some(Color::LightYellow),
+        _ => None,
+    }
+}
+
+fn level_fg_color(level: LogLevel) -> Option<Color> {
+    match level {
+        LogLevel::Fatal | LogLevel::Error | LogLevel::Warning => Some(Color::Black),
+        _ => None,
+    }
+}
+
+/// How a message at `level` is drawn
+pub fn level_style(level: LogLevel) -> Style {
+    let mut style = Style::default();
+    if let Some(bg) = level_bg_color(level) {
+        style = style.bg(bg);
+    }
+    if let Some(fg) = level_fg_color(level) {
+        style = style.fg(fg);
+    }
+    style
+}
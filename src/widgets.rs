@@ -1,5 +1,6 @@
 pub mod fps_overlay;
 pub mod log;
+pub mod shell;
 pub mod status;
 
 #[derive(Copy, Clone)]
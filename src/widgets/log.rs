@@ -1,56 +1,317 @@
-use std::{ops::Range, pin::Pin};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    ops::Range,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
+use chrono::{Local, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc};
 use futures::Stream;
+use regex::Regex;
 use tokio_stream::StreamExt;
 use tui::{
-    layout::Constraint,
+    buffer::Buffer,
+    layout::{Constraint, Rect},
     style::{Color, Modifier, Style},
     widgets::{Block, Cell, Row, StatefulWidget, Table, Widget},
 };
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::{
-    commands::adb::{LogBuffer, LogLevel, LogMessage, LogcatDecodeError},
+    commands::adb::{
+        self, log_id_name, LogBuffer, LogLevel, LogMessage, LogcatDecodeError, LogcatOptions,
+        TextLogBuffer,
+    },
+    theme::{level_bg_color, level_style, selection_style},
     widgets::Control,
 };
 
-fn level_to_bg_color(level: LogLevel) -> Option<Color> {
-    match level {
-        LogLevel::Fatal => Some(Color::Red),
-        LogLevel::Error => Some(Color::LightRed),
-        LogLevel::Warning => Some(Color::LightYellow),
-        _ => None,
+/// Picks a stable color for a tag so its lines can be followed by eye
+pub fn tag_color(tag: &str) -> Color {
+    const PALETTE: [Color; 12] = [
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightMagenta,
+        Color::LightCyan,
+    ];
+
+    let mut hasher = DefaultHasher::new();
+    tag.hash(&mut hasher);
+    PALETTE[(hasher.finish() % PALETTE.len() as u64) as usize]
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, serde::Serialize, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorBy {
+    /// Only color rows by severity
+    #[default]
+    Level,
+    /// Additionally give each tag its own color
+    Tag,
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, serde::Serialize, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeFormat {
+    /// The time each message was logged, in UTC
+    #[default]
+    Absolute,
+    /// The time each message was logged, in this computer's time zone
+    Local,
+    /// How long ago each message was logged, like `1m12s`
+    Relative,
+}
+
+impl TimeFormat {
+    pub fn toggle(self) -> TimeFormat {
+        match self {
+            TimeFormat::Absolute => TimeFormat::Local,
+            TimeFormat::Local => TimeFormat::Relative,
+            TimeFormat::Relative => TimeFormat::Absolute,
+        }
+    }
+}
+
+/// The UTC time that `local` is on a wall clock in `zone`. A DST change skips or repeats an hour of
+/// wall clock times, which have no single answer, so those are taken as UTC already and `false` is
+/// returned as a marker that the result is off by the zone's offset.
+pub fn local_to_utc<Tz: TimeZone>(zone: &Tz, local: NaiveDateTime) -> (NaiveDateTime, bool) {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(time) => (time.naive_utc(), true),
+        LocalResult::Ambiguous(..) | LocalResult::None => (local, false),
+    }
+}
+
+/// How long ago something happened, like `now`, `3s`, `1m12s` or `2h5m`
+pub fn format_age(age: chrono::Duration) -> String {
+    let secs = age.num_seconds();
+    match secs {
+        // a device clock ahead of the host's makes messages appear to come from the future
+        ..=0 => "now".to_string(),
+        1..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m{}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{}h", secs / 86400, secs % 86400 / 3600),
     }
 }
 
-fn level_to_fg_color(level: LogLevel) -> Option<Color> {
-    match level {
-        LogLevel::Fatal | LogLevel::Error | LogLevel::Warning => Some(Color::Black),
-        _ => None,
+// width of the tag column in the normal layout, and the most a tag takes up in the compact one
+const TAG_WIDTH: usize = 20;
+
+// wide enough for the largest pid Linux hands out, 2^22
+const ID_WIDTH: usize = 7;
+
+/// Shortens `tag` to at most `width` characters, marking the cut with `…`. Dotted tags like class
+/// names are cut in the middle instead, since their end is usually the meaningful part.
+pub fn truncate_tag(tag: &str, width: usize) -> String {
+    if tag.width() <= width {
+        return tag.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    match tag.split_once('.') {
+        Some((head, _)) => {
+            let head = take_width(head, (width - 1) / 3);
+            let tail = take_width_from_end(tag, width - 1 - head.width());
+            format!("{head}…{tail}")
+        }
+        None => format!("{}…", take_width(tag, width - 1)),
+    }
+}
+
+// the longest prefix of `s` that fits in `width` columns. wide characters like CJK take two
+fn take_width(s: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in s.char_indices() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+// the longest suffix of `s` that fits in `width` columns
+fn take_width_from_end(s: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in s.char_indices().rev() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &s[i + c.len_utf8()..];
+        }
     }
+    s
 }
 
-fn style_from_level(level: LogLevel) -> Style {
-    let mut style = Style::default();
-    if let Some(bg) = level_to_bg_color(level) {
-        style = style.bg(bg);
+// renders bytes that failed to decode, escaping anything that isn't printable ascii
+fn escape_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect()
+}
+
+/// A `File.kt:123` source reference as drawn on screen, e.g. from a stack trace
+pub struct SourceLink {
+    pub x: u16,
+    pub y: u16,
+    pub cells: Vec<tui::buffer::Cell>,
+    pub file: String,
+    pub line: u32,
+}
+
+// finds source references in what's already been drawn to `area`, so they're found wherever the
+// table laid them out and however they were clipped
+fn find_source_links(area: Rect, buf: &Buffer) -> Vec<SourceLink> {
+    lazy_static::lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"(?P<file>[[:word:]$]+\.(?:kt|java)):(?P<line>[[:digit:]]+)").unwrap();
     }
-    if let Some(fg) = level_to_fg_color(level) {
-        style = style.fg(fg);
+
+    let mut links = Vec::new();
+    for y in area.top()..area.bottom() {
+        // byte offset in `text` at which each cell's symbol starts
+        let mut text = String::new();
+        let mut offsets = Vec::new();
+        for x in area.left()..area.right() {
+            offsets.push(text.len());
+            text.push_str(&buf.get(x, y).symbol);
+        }
+
+        for captures in RE.captures_iter(&text) {
+            let range = captures.get(0).unwrap().range();
+            let Ok(line) = captures["line"].parse() else {
+                continue;
+            };
+            let cells: Vec<_> = (area.left()..area.right())
+                .zip(&offsets)
+                .filter(|(_, offset)| range.contains(offset))
+                .map(|(x, _)| buf.get(x, y).clone())
+                .collect();
+            links.push(SourceLink {
+                x: area.left() + offsets.iter().position(|o| *o == range.start).unwrap() as u16,
+                y,
+                cells,
+                file: captures["file"].to_string(),
+                line,
+            });
+        }
+    }
+    links
+}
+
+fn is_at_or_above(message: &LogMessage, level: LogLevel) -> bool {
+    matches!(&message.buffer, LogBuffer::TextLog(buffer) if buffer.level >= level)
+}
+
+// `text` is expected to be lowercase already
+fn contains_text(message: &LogMessage, text: &str) -> bool {
+    match &message.buffer {
+        LogBuffer::TextLog(buffer) => {
+            buffer.message.to_lowercase().contains(text) || buffer.tag.to_lowercase().contains(text)
+        }
+        _ => false,
     }
-    style
 }
 
 pub struct Log<'a> {
     block: Option<Block<'a>>,
+    color_by: ColorBy,
+    time_format: TimeFormat,
+    compact: bool,
+    multiline: bool,
+    show_pid: bool,
+    show_tid: bool,
+    line_numbers: bool,
+    source_links: bool,
+    plain: bool,
 }
 
 impl<'a> Log<'a> {
     pub fn new() -> Self {
         Self {
             block: Default::default(),
+            color_by: Default::default(),
+            time_format: Default::default(),
+            compact: false,
+            multiline: false,
+            show_pid: false,
+            show_tid: false,
+            line_numbers: false,
+            source_links: false,
+            plain: false,
         }
     }
 
+    /// Drops the header, the tag and time columns and the level colors, for text that didn't come
+    /// from logcat
+    pub fn plain(mut self, plain: bool) -> Self {
+        self.plain = plain;
+        self
+    }
+
+    /// Drops the header and folds the tag into the message to fit more on screen
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    /// Splits messages with embedded newlines, like stack traces, across several rows
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Adds a column with the id of the process that logged each message
+    pub fn show_pid(mut self, show_pid: bool) -> Self {
+        self.show_pid = show_pid;
+        self
+    }
+
+    /// Adds a column with the id of the thread that logged each message
+    pub fn show_tid(mut self, show_tid: bool) -> Self {
+        self.show_tid = show_tid;
+        self
+    }
+
+    /// Adds a leftmost column numbering each message from the first one kept, so lines can be
+    /// referred to. Markers and dividers aren't counted.
+    pub fn line_numbers(mut self, line_numbers: bool) -> Self {
+        self.line_numbers = line_numbers;
+        self
+    }
+
+    /// Collects the source references drawn into [`LogState::source_links`]
+    pub fn source_links(mut self, source_links: bool) -> Self {
+        self.source_links = source_links;
+        self
+    }
+
+    pub fn color_by(mut self, color_by: ColorBy) -> Self {
+        self.color_by = color_by;
+        self
+    }
+
+    /// Whether the time column shows when messages were logged or how long ago, relative to when
+    /// it's drawn
+    pub fn time_format(mut self, time_format: TimeFormat) -> Self {
+        self.time_format = time_format;
+        self
+    }
+
     pub fn block(mut self, block: Block<'a>) -> Self {
         self.block = Some(block);
         self
@@ -69,36 +330,223 @@ pub struct LogState {
     logs: Vec<LogMessage>,
     selected: Option<usize>,
     anchor: Anchor,
+    // log ids to keep, or every buffer if empty
+    buffers: Vec<u32>,
+    dropped: usize,
+    decode_errors: usize,
+    // when the session started, until the divider marking it has been inserted
+    session_start: Option<NaiveDateTime>,
+    source_links: Vec<SourceLink>,
+    // when each message within the last `RATE_WINDOW` arrived
+    arrivals: VecDeque<Instant>,
+    // adb stopped sending messages, usually because the device went away
+    ended: bool,
+    // rows that fit in the last frame, for paging
+    height: usize,
+    // log ids whose "beginning of" markers were cleared away, to be marked again before their
+    // next message
+    cleared_log_ids: HashSet<u32>,
+    // indices in `logs` of the markers and dividers, which aren't given line numbers
+    unnumbered: Vec<usize>,
 }
 
+// how far back the logs per second are averaged
+const RATE_WINDOW: Duration = Duration::from_secs(3);
+
 impl LogState {
-    pub fn new(serial: &str) -> Self {
-        let log_stream = Box::pin(crate::commands::adb::logcat(serial));
+    pub fn new(serial: &str, options: &LogcatOptions) -> Self {
+        Self::from_stream(adb::logcat(adb::transport(), serial, options))
+    }
+
+    /// Pages through lines of text, like a shell command's output, starting from the top. Each line
+    /// is a message without a tag, to be drawn with [`Log::plain`].
+    pub fn text(lines: impl Stream<Item = tokio::io::Result<String>> + 'static) -> Self {
+        let now = Utc::now().naive_utc();
+        let log_stream = lines.map(move |line| {
+            // errors are shown where they happened in the output
+            let message = line.unwrap_or_else(|err| format!("error: {err}"));
+            Ok(LogMessage {
+                timestamp: now,
+                pid: 0,
+                tid: 0,
+                lid: None,
+                uid: None,
+                buffer: LogBuffer::TextLog(TextLogBuffer {
+                    level: LogLevel::Info,
+                    tag: String::new(),
+                    message,
+                }),
+                raw: None,
+            })
+        });
         Self {
-            log_stream,
+            anchor: Anchor::Top(0),
+            session_start: None,
+            ..Self::from_stream(log_stream)
+        }
+    }
+
+    fn from_stream(
+        log_stream: impl Stream<Item = Result<LogMessage, LogcatDecodeError>> + 'static,
+    ) -> Self {
+        Self {
+            log_stream: Box::pin(log_stream),
             logs: Default::default(),
             selected: None,
             anchor: Anchor::Autoscroll,
+            buffers: Vec::new(),
+            dropped: 0,
+            decode_errors: 0,
+            session_start: Some(Utc::now().naive_utc()),
+            source_links: Vec::new(),
+            arrivals: VecDeque::new(),
+            ended: false,
+            height: 0,
+            cleared_log_ids: HashSet::new(),
+            unnumbered: Vec::new(),
         }
     }
 
+    /// Only keeps messages from the given log ids. Messages without a log id are always kept.
+    pub fn set_buffer_filter(&mut self, buffers: Vec<u32>) {
+        self.buffers = buffers;
+    }
+
+    /// Waits for the next message. Never completes once adb has stopped sending them.
     pub async fn poll(&mut self) {
+        if self.ended {
+            return futures::future::pending().await;
+        }
+
         if let Some(message) = self.log_stream.next().await {
             match message {
                 Ok(message) => {
-                    self.logs.push(message);
+                    self.dropped += message.dropped_count().unwrap_or(0);
+                    self.record_arrival();
+
+                    // adb dumps what's already in the buffers first, so the divider goes before
+                    // the first message logged after the session started
+                    if let Some(session_start) = self.session_start {
+                        if message.timestamp >= session_start {
+                            self.push_divider(session_start, "session start");
+                            self.session_start = None;
+                        }
+                    }
+
+                    let filtered = match message.lid {
+                        Some(lid) => !self.buffers.is_empty() && !self.buffers.contains(&lid),
+                        None => false,
+                    };
+                    if !filtered {
+                        if let Some(lid) =
+                            message.lid.filter(|lid| self.cleared_log_ids.remove(lid))
+                        {
+                            if !matches!(message.buffer, LogBuffer::Marker(_)) {
+                                self.push_marker(message.timestamp, lid);
+                            }
+                        }
+                        self.push(message);
+                    }
                     return;
                 }
-                _ => {}
+                // the stream ends right after, which is reported as a disconnect
+                Err(err) if err.is_device_offline() => {
+                    tracing::debug!("logcat stopped, device offline");
+                }
+                Err(err) => {
+                    self.decode_errors += 1;
+                    tracing::debug!("logcat decode error: {err:?}");
+                }
+            }
+        } else {
+            self.ended = true;
+        }
+    }
+
+    fn record_arrival(&mut self) {
+        let now = Instant::now();
+        self.arrivals.push_back(now);
+        while let Some(oldest) = self.arrivals.front() {
+            if now - *oldest < RATE_WINDOW {
+                break;
             }
+            self.arrivals.pop_front();
         }
     }
 
+    /// Messages received per second, averaged over the last few seconds. Counts everything adb
+    /// sends, including messages the buffer filter hides.
+    pub fn rate(&self) -> f32 {
+        let recent = self
+            .arrivals
+            .iter()
+            .rev()
+            .take_while(|arrival| arrival.elapsed() < RATE_WINDOW)
+            .count();
+        recent as f32 / RATE_WINDOW.as_secs_f32()
+    }
+
+    fn push(&mut self, message: LogMessage) {
+        if matches!(message.buffer, LogBuffer::Marker(_) | LogBuffer::Divider(_)) {
+            self.unnumbered.push(self.logs.len());
+        }
+        self.logs.push(message);
+    }
+
+    // the line number of the message at `index`, counting only the ones from the device
+    fn line_number(&self, index: usize) -> usize {
+        index + 1
+            - self
+                .unnumbered
+                .partition_point(|unnumbered| *unnumbered < index)
+    }
+
+    fn push_divider(&mut self, timestamp: NaiveDateTime, label: &str) {
+        self.push(LogMessage {
+            timestamp,
+            pid: 0,
+            tid: 0,
+            lid: None,
+            uid: None,
+            buffer: LogBuffer::Divider(label.to_string()),
+            raw: None,
+        });
+    }
+
+    fn push_marker(&mut self, timestamp: NaiveDateTime, lid: u32) {
+        let name = log_id_name(lid).map_or_else(|| format!("log id {lid}"), str::to_owned);
+        self.push(LogMessage {
+            timestamp,
+            pid: 0,
+            tid: 0,
+            lid: Some(lid),
+            uid: None,
+            buffer: LogBuffer::Marker(format!("beginning of {name}")),
+            raw: None,
+        });
+    }
+
+    /// Empties the view, leaving a divider to show where it was cleared
+    pub fn clear(&mut self) {
+        self.cleared_log_ids
+            .extend(self.logs.iter().filter_map(|message| message.lid));
+        self.logs.clear();
+        self.unnumbered.clear();
+        self.selected = None;
+        self.anchor = Anchor::Autoscroll;
+        self.session_start = None;
+
+        let now = Utc::now().naive_utc();
+        self.push_divider(now, &format!("cleared at {}", now.format("%H:%M:%S")));
+    }
+
     pub fn control(&mut self, control: Control) {
         match control {
             Control::Up => {
                 if let Some(selected) = self.selected {
                     self.selected = Some(selected.saturating_sub(1));
+                } else if let Some(top) = self.unselected_top() {
+                    self.selected = Some(top);
                 } else if self.logs.len() > 0 {
                     self.selected = Some(self.logs.len() - 1);
                 }
@@ -106,6 +554,8 @@ impl LogState {
             Control::Down => {
                 if let Some(selected) = self.selected {
                     self.selected = Some((selected + 1).min(self.logs.len() - 1));
+                } else if let Some(top) = self.unselected_top() {
+                    self.selected = Some(top);
                 }
             }
             Control::Bottom => {
@@ -120,6 +570,172 @@ impl LogState {
         }
     }
 
+    // the first row in view when scrolled to the top without a selection, as text starts out
+    fn unselected_top(&self) -> Option<usize> {
+        match self.anchor {
+            Anchor::Top(top) if top < self.logs.len() => Some(top),
+            _ => None,
+        }
+    }
+
+    /// Moves the selection by a screenful, up if `up` is set
+    pub fn page(&mut self, up: bool) {
+        let control = if up { Control::Up } else { Control::Down };
+        for _ in 0..self.height.saturating_sub(1).max(1) {
+            self.control(control);
+        }
+    }
+
+    // selects the first match after the selection, returning whether there was one
+    fn select_next(&mut self, matches: impl Fn(&LogMessage) -> bool) -> bool {
+        let start = match self.selected {
+            Some(selected) => selected + 1,
+            None => self.unselected_top().unwrap_or(self.logs.len()),
+        };
+        if let Some(offset) = self.logs[start.min(self.logs.len())..]
+            .iter()
+            .position(matches)
+        {
+            self.selected = Some(start + offset);
+            return true;
+        }
+        false
+    }
+
+    // selects the last match before the selection, returning whether there was one
+    fn select_prev(&mut self, matches: impl Fn(&LogMessage) -> bool) -> bool {
+        let end = self
+            .selected
+            .unwrap_or(self.logs.len())
+            .min(self.logs.len());
+        if let Some(index) = self.logs[..end].iter().rposition(matches) {
+            self.selected = Some(index);
+            return true;
+        }
+        false
+    }
+
+    /// Selects the next message at `level` or above after the selection, if there is one
+    pub fn next_at_or_above(&mut self, level: LogLevel) {
+        self.select_next(|message| is_at_or_above(message, level));
+    }
+
+    /// Selects the previous message at `level` or above before the selection, searching from the
+    /// newest message when nothing is selected
+    pub fn prev_at_or_above(&mut self, level: LogLevel) {
+        self.select_prev(|message| is_at_or_above(message, level));
+    }
+
+    /// Selects the next message whose tag or text contains `text`, ignoring case. Returns false if
+    /// there isn't one.
+    pub fn next_containing(&mut self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.select_next(|message| contains_text(message, &text))
+    }
+
+    /// Like `next_containing`, but searching backwards from the selection
+    pub fn prev_containing(&mut self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.select_prev(|message| contains_text(message, &text))
+    }
+
+    /// Selects the first message at or after `time` on the day of the most recent message.
+    pub fn jump_to_time(&mut self, time: NaiveTime) {
+        let Some(last) = self.logs.last() else {
+            return;
+        };
+
+        let mut target = last.timestamp.date().and_time(time);
+        if target > last.timestamp {
+            // a time later than the newest message must be from the day before
+            target -= chrono::Duration::days(1);
+        }
+
+        self.select_timestamp(target);
+    }
+
+    /// Like `jump_to_time`, but with `time` on this computer's clock. Returns false if a DST change
+    /// skipped or repeated that time, in which case it's read as UTC instead.
+    pub fn jump_to_local_time(&mut self, time: NaiveTime) -> bool {
+        let Some(last) = self.logs.last() else {
+            return true;
+        };
+
+        let last = Local.from_utc_datetime(&last.timestamp).naive_local();
+        let mut target = last.date().and_time(time);
+        if target > last {
+            target -= chrono::Duration::days(1);
+        }
+
+        let (target, exact) = local_to_utc(&Local, target);
+        self.select_timestamp(target);
+        exact
+    }
+
+    /// Selects the first message at or after `timestamp`, or the last message if they're all older
+    pub fn select_timestamp(&mut self, timestamp: NaiveDateTime) {
+        if self.logs.is_empty() {
+            return;
+        }
+
+        let index = self.logs.partition_point(|m| m.timestamp < timestamp);
+        self.selected = Some(index.min(self.logs.len() - 1));
+    }
+
+    /// Where the selected message is in [`LogState::logs`]
+    pub fn selected_index(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn selected_message(&self) -> Option<&LogMessage> {
+        self.logs.get(self.selected?)
+    }
+
+    /// When the selected message was logged
+    pub fn selected_timestamp(&self) -> Option<NaiveDateTime> {
+        Some(self.selected_message()?.timestamp)
+    }
+
+    /// Switches between following new messages and freezing the viewport where it is.
+    pub fn toggle_follow(&mut self) {
+        self.anchor = match self.anchor {
+            Anchor::Autoscroll => Anchor::Bottom(self.logs.len().saturating_sub(1)),
+            Anchor::Bottom(_) | Anchor::Top(_) => {
+                self.selected = None;
+                Anchor::Autoscroll
+            }
+        };
+    }
+
+    /// Messages the device reported dropping, so the view isn't complete
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Entries that couldn't be decoded at all, which usually means a parser bug
+    pub fn decode_errors(&self) -> usize {
+        self.decode_errors
+    }
+
+    /// Every message kept, after the buffer filter
+    pub fn logs(&self) -> &[LogMessage] {
+        &self.logs
+    }
+
+    /// Source references in the last frame, if the widget was asked to collect them
+    pub fn source_links(&self) -> &[SourceLink] {
+        &self.source_links
+    }
+
+    /// Whether adb has stopped sending messages
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    pub fn is_following(&self) -> bool {
+        matches!(self.anchor, Anchor::Autoscroll)
+    }
+
     fn rows_to_display(&self, height: usize) -> Range<usize> {
         if self.logs.len() <= height {
             return 0..self.logs.len();
@@ -128,7 +744,10 @@ impl LogState {
         match self.anchor {
             Anchor::Autoscroll => self.logs.len() - height..self.logs.len(),
             Anchor::Top(index) => index..index + height,
-            Anchor::Bottom(index) => index - height + 1..index + 1,
+            Anchor::Bottom(index) => {
+                let start = (index + 1).saturating_sub(height);
+                start..start + height
+            }
         }
     }
 }
@@ -142,13 +761,77 @@ impl<'a> StatefulWidget for Log<'a> {
         buf: &mut tui::buffer::Buffer,
         state: &mut Self::State,
     ) {
-        let header = Row::new(["Tag", "Date", "Message"]);
+        // table cells can't be aligned, so the ids are right aligned by padding them instead
+        let ids = |pid: &dyn ToString, tid: &dyn ToString| {
+            let mut cells = Vec::new();
+            if self.show_pid {
+                cells.push(Cell::from(format!("{:>ID_WIDTH$}", pid.to_string())));
+            }
+            if self.show_tid {
+                cells.push(Cell::from(format!("{:>ID_WIDTH$}", tid.to_string())));
+            }
+            cells
+        };
+        let num_columns = if self.plain {
+            1
+        } else if self.compact {
+            2
+        } else {
+            3
+        } + self.line_numbers as usize
+            + self.show_pid as usize
+            + self.show_tid as usize;
+
+        // wide enough for the number of the last message, right aligned like the ids
+        let number_width = (state.logs.len() - state.unnumbered.len())
+            .to_string()
+            .len();
+        let number = |cell: String| {
+            self.line_numbers
+                .then(|| Cell::from(format!("{cell:>number_width$}")))
+        };
 
-        let mut num_rows = area.height - 1;
+        let now = Utc::now().naive_utc();
+        let time = |timestamp: NaiveDateTime| {
+            let timestamp = match self.time_format {
+                TimeFormat::Relative => return format_age(now - timestamp),
+                TimeFormat::Absolute => timestamp,
+                // can't fail, unlike going from local to UTC
+                TimeFormat::Local => Local.from_utc_datetime(&timestamp).naive_local(),
+            };
+            if self.compact {
+                timestamp.format("%H:%M:%S%.3f").to_string()
+            } else {
+                timestamp.to_string()
+            }
+        };
+
+        let header = Row::new(
+            number("#".to_string())
+                .into_iter()
+                .chain([
+                    Cell::from("Tag"),
+                    Cell::from(match self.time_format {
+                        TimeFormat::Absolute | TimeFormat::Local => "Date",
+                        TimeFormat::Relative => "Age",
+                    }),
+                ])
+                .chain(ids(&"PID", &"TID"))
+                .chain([Cell::from("Message")]),
+        );
+
+        // plain text has no header either
+        let header_shown = !self.compact && !self.plain;
+
+        let mut num_rows = area.height;
+        if header_shown {
+            num_rows = num_rows.saturating_sub(1);
+        }
         if self.block.is_some() {
-            num_rows -= 2;
+            num_rows = num_rows.saturating_sub(2);
         }
 
+        state.height = num_rows as usize;
         let rows_to_display = state.rows_to_display(num_rows as usize);
 
         // update anchoring
@@ -167,41 +850,357 @@ impl<'a> StatefulWidget for Log<'a> {
             .iter()
             .enumerate()
             .map(|(i, m)| (i + rows_to_display.start, m))
-            .map(|(i, message)| {
-                let LogBuffer::TextLog(ref buffer) = message.buffer else { panic!() };
+            .flat_map(|(i, message)| {
+                if let LogBuffer::Divider(label) = &message.buffer {
+                    // cells can't span columns, so rule off every one of them
+                    let rule = "─".repeat(area.width as usize);
+                    let label = format!("── {label} {rule}");
+                    let row = Row::new(
+                        std::iter::repeat_n(Cell::from(rule), num_columns - 1)
+                            .chain([Cell::from(label)]),
+                    );
 
-                let mut base_style = style_from_level(buffer.level);
-                if Some(i) == state.selected {
-                    base_style = base_style.patch(
+                    let mut style = Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD);
+                    if Some(i) == state.selected {
+                        // keep the divider's own color so it still reads as one
+                        if let Some(bg) = selection_style().bg {
+                            style = style.bg(bg);
+                        }
+                    }
+                    return vec![row.style(style)];
+                }
+
+                let (tag, text, mut base_style) = match &message.buffer {
+                    LogBuffer::TextLog(buffer) if self.plain => (
+                        Cow::from(""),
+                        Cow::from(buffer.message.as_str()),
+                        Style::default(),
+                    ),
+                    LogBuffer::TextLog(buffer) => {
+                        let mut style = level_style(buffer.level);
+                        // warning and above keep their level colors so severity stays obvious
+                        if let (ColorBy::Tag, None) = (self.color_by, level_bg_color(buffer.level))
+                        {
+                            style = style.fg(tag_color(&buffer.tag));
+                        }
+
+                        (
+                            Cow::from(truncate_tag(&buffer.tag, TAG_WIDTH)),
+                            Cow::from(buffer.message.as_str()),
+                            style,
+                        )
+                    }
+                    LogBuffer::Unknown(bytes) => (
+                        Cow::from("<unknown>"),
+                        Cow::from(escape_bytes(bytes)),
+                        Style::default().add_modifier(Modifier::DIM),
+                    ),
+                    LogBuffer::Marker(text) | LogBuffer::Divider(text) => (
+                        Cow::from("---------"),
+                        Cow::from(text.as_str()),
                         Style::default()
-                            .bg(Color::Gray)
-                            .fg(Color::Black)
+                            .fg(Color::Cyan)
                             .add_modifier(Modifier::BOLD),
-                    );
+                    ),
+                    LogBuffer::BinaryLog(buffer) => (
+                        Cow::from(format!("<{}>", buffer.log_id.name())),
+                        Cow::from(format!("<binary event tag {}>", buffer.tag)),
+                        Style::default(),
+                    ),
+                };
+                if Some(i) == state.selected {
+                    base_style = base_style.patch(selection_style());
                 }
 
-                Row::new([
-                    Cell::from(buffer.tag.as_str()),
-                    Cell::from(message.timestamp.to_string()),
-                    Cell::from(buffer.message.as_str()),
-                ])
-                .style(base_style)
+                // continuation lines leave the tag and time columns blank
+                let lines: Vec<Cow<str>> = if self.multiline && text.contains('\n') {
+                    text.lines()
+                        .map(|line| Cow::from(line.to_string()))
+                        .collect()
+                } else {
+                    vec![text]
+                };
+                let mut lines = lines.into_iter();
+                let first = lines.next().unwrap_or_default();
+                let line_number = match &message.buffer {
+                    LogBuffer::Marker(_) => String::new(),
+                    _ => state.line_number(i).to_string(),
+                };
+                let first = if self.plain {
+                    Row::new(
+                        number(line_number)
+                            .into_iter()
+                            .chain(ids(&message.pid, &message.tid))
+                            .chain([Cell::from(first)]),
+                    )
+                } else if self.compact {
+                    // fold the tag into the message to give it the reclaimed width
+                    let text = match &message.buffer {
+                        LogBuffer::TextLog(buffer) => {
+                            format!("{}/{tag}: {first}", buffer.level.as_char())
+                        }
+                        _ => format!("{tag} {first}"),
+                    };
+                    Row::new(
+                        number(line_number)
+                            .into_iter()
+                            .chain([Cell::from(time(message.timestamp))])
+                            .chain(ids(&message.pid, &message.tid))
+                            .chain([Cell::from(text)]),
+                    )
+                } else {
+                    Row::new(
+                        number(line_number)
+                            .into_iter()
+                            .chain([Cell::from(tag), Cell::from(time(message.timestamp))])
+                            .chain(ids(&message.pid, &message.tid))
+                            .chain([Cell::from(first)]),
+                    )
+                };
+
+                std::iter::once(first)
+                    .chain(lines.map(|line| {
+                        Row::new(
+                            std::iter::repeat_n(Cell::from(""), num_columns - 1)
+                                .chain([Cell::from(line)]),
+                        )
+                    }))
+                    .map(|row| row.style(base_style))
+                    .collect::<Vec<_>>()
             })
-            .take(num_rows as usize)
             .collect::<Vec<_>>();
 
-        let mut table = Table::new(rows)
-            .header(header.style(Style::default().bg(Color::Gray).fg(Color::Black)))
-            .widths(&[
-                Constraint::Length(20),
-                Constraint::Length(20),
-                Constraint::Percentage(100),
-            ]);
+        // messages can span several rows, so trim from whichever end the viewport is anchored to
+        let excess = rows.len().saturating_sub(num_rows as usize);
+        let rows: Vec<_> = match state.anchor {
+            Anchor::Top(_) => rows.into_iter().take(num_rows as usize).collect(),
+            Anchor::Autoscroll | Anchor::Bottom(_) => rows.into_iter().skip(excess).collect(),
+        };
 
+        let mut widths = Vec::new();
+        if self.line_numbers {
+            widths.push(Constraint::Length(number_width as u16));
+        }
+        if self.compact && !self.plain {
+            widths.push(Constraint::Length(12));
+        } else if !self.plain {
+            widths.extend([Constraint::Length(TAG_WIDTH as u16), Constraint::Length(20)]);
+        }
+        widths.resize(num_columns - 1, Constraint::Length(ID_WIDTH as u16));
+        widths.push(Constraint::Percentage(100));
+
+        let mut table = Table::new(rows).widths(&widths);
+        if header_shown {
+            table = table.header(header.style(Style::default().bg(Color::Gray).fg(Color::Black)));
+        }
+
+        let mut table_area = area;
         if let Some(block) = self.block {
+            table_area = block.inner(area);
             table = table.block(block);
         }
 
-        Widget::render(table, area, buf)
+        Widget::render(table, area, buf);
+
+        state.source_links = if self.source_links {
+            find_source_links(table_area, buf)
+        } else {
+            Vec::new()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, NaiveDate};
+
+    use super::*;
+
+    // US eastern time in 2023, when clocks went forward at 2am on March 12 and back at 2am on
+    // November 5
+    #[derive(Clone)]
+    struct Eastern;
+
+    impl Eastern {
+        fn est() -> FixedOffset {
+            FixedOffset::west_opt(5 * 3600).unwrap()
+        }
+
+        fn edt() -> FixedOffset {
+            FixedOffset::west_opt(4 * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for Eastern {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Eastern
+        }
+
+        fn offset_from_local_date(&self, _: &NaiveDate) -> LocalResult<FixedOffset> {
+            unimplemented!()
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // an offset fits if the UTC time it gives has that offset
+            let fits: Vec<_> = [Eastern::est(), Eastern::edt()]
+                .into_iter()
+                .filter(|offset| self.offset_from_utc_datetime(&(*local - *offset)) == *offset)
+                .collect();
+            match fits[..] {
+                [offset] => LocalResult::Single(offset),
+                // daylight time is the earlier of the two instants
+                [est, edt] => LocalResult::Ambiguous(edt, est),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, _: &NaiveDate) -> FixedOffset {
+            unimplemented!()
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let utc_at = |month, day, hour| {
+                NaiveDate::from_ymd_opt(2023, month, day)
+                    .unwrap()
+                    .and_hms_opt(hour, 0, 0)
+                    .unwrap()
+            };
+            if (utc_at(3, 12, 7)..utc_at(11, 5, 6)).contains(utc) {
+                Eastern::edt()
+            } else {
+                Eastern::est()
+            }
+        }
+    }
+
+    fn at(month: u32, day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, month, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn local_to_utc_converts_around_dst() {
+        assert_eq!(
+            local_to_utc(&Eastern, at(3, 12, 1, 30)),
+            (at(3, 12, 6, 30), true)
+        );
+        assert_eq!(
+            local_to_utc(&Eastern, at(3, 12, 3, 30)),
+            (at(3, 12, 7, 30), true)
+        );
+        assert_eq!(
+            local_to_utc(&Eastern, at(11, 5, 2, 30)),
+            (at(11, 5, 7, 30), true)
+        );
+    }
+
+    #[test]
+    fn local_to_utc_falls_back_to_utc_for_skipped_times() {
+        assert_eq!(
+            local_to_utc(&Eastern, at(3, 12, 2, 30)),
+            (at(3, 12, 2, 30), false)
+        );
+    }
+
+    #[test]
+    fn local_to_utc_falls_back_to_utc_for_repeated_times() {
+        assert_eq!(
+            local_to_utc(&Eastern, at(11, 5, 1, 30)),
+            (at(11, 5, 1, 30), false)
+        );
+    }
+
+    #[tokio::test]
+    async fn text_search_starts_from_the_top_without_wrapping() {
+        let lines = ["Service A", "  state=idle", "Service B", "  state=RUNNING"];
+        let mut log = LogState::text(futures::stream::iter(
+            lines.map(|line| Ok(line.to_string())),
+        ));
+        for _ in lines {
+            log.poll().await;
+        }
+
+        assert!(log.next_containing("state="));
+        assert_eq!(log.selected_index(), Some(1));
+        assert!(log.next_containing("running"));
+        assert_eq!(log.selected_index(), Some(3));
+        assert!(!log.next_containing("service"));
+        assert_eq!(log.selected_index(), Some(3));
+        assert!(log.prev_containing("service"));
+        assert_eq!(log.selected_index(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn line_numbers_skip_markers_and_dividers() {
+        let message = |buffer| LogMessage {
+            timestamp: at(1, 1, 0, 0),
+            pid: 0,
+            tid: 0,
+            lid: Some(0),
+            uid: None,
+            buffer,
+            raw: None,
+        };
+        let text = |text: &str| {
+            message(LogBuffer::TextLog(TextLogBuffer {
+                level: LogLevel::Info,
+                tag: "Tag".to_string(),
+                message: text.to_string(),
+            }))
+        };
+        let messages = [
+            message(LogBuffer::Marker("beginning of main".to_string())),
+            text("first"),
+            text("second"),
+        ];
+        let mut log = LogState::from_stream(futures::stream::iter(messages.map(Ok)));
+        for _ in 0..3 {
+            log.poll().await;
+        }
+        log.push_divider(at(1, 1, 0, 0), "session start");
+        log.push(text("third"));
+
+        let numbers = [1, 2, 4].map(|i| log.line_number(i));
+        assert_eq!(numbers, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn clearing_marks_each_buffer_again() {
+        let text = |text: &str| LogMessage {
+            timestamp: at(1, 1, 0, 0),
+            pid: 0,
+            tid: 0,
+            lid: Some(0),
+            uid: None,
+            buffer: LogBuffer::TextLog(TextLogBuffer {
+                level: LogLevel::Info,
+                tag: "Tag".to_string(),
+                message: text.to_string(),
+            }),
+            raw: None,
+        };
+        let messages = [text("before"), text("after")];
+        let mut log = LogState::from_stream(futures::stream::iter(messages.map(Ok)));
+        log.poll().await;
+        log.clear();
+        log.poll().await;
+
+        let rows: Vec<_> = log
+            .logs()
+            .iter()
+            .map(|message| match &message.buffer {
+                LogBuffer::TextLog(buffer) => buffer.message.clone(),
+                LogBuffer::Marker(text) => text.clone(),
+                LogBuffer::Divider(_) => "divider".to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(rows, ["divider", "beginning of main", "after"]);
     }
 }
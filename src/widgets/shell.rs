@@ -0,0 +1,237 @@
+use std::{collections::VecDeque, pin::Pin};
+
+use crossterm::event::{KeyCode, KeyEvent};
+use futures::Stream;
+use tokio_stream::StreamExt;
+use tui::{
+    layout::Rect,
+    style::{Color, Style},
+    widgets::{Block, Borders, Clear, Paragraph, StatefulWidget, Widget},
+};
+
+use crate::commands::adb;
+
+// how many commands are remembered for up and down to cycle through
+const HISTORY_LEN: usize = 50;
+
+/// A popup that reads an `adb shell` command and then streams its output
+pub struct ShellOverlay {}
+
+impl ShellOverlay {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+enum Stage {
+    Input(String),
+    Running {
+        command: String,
+        output: Pin<Box<dyn Stream<Item = tokio::io::Result<String>>>>,
+        lines: Vec<String>,
+        done: bool,
+        // lines scrolled up from the bottom, 0 follows new output
+        scroll: usize,
+    },
+}
+
+pub struct ShellOverlayState {
+    serial: Option<String>,
+    stage: Stage,
+    // commands run this session, oldest first. Handed back by `into_history` when the overlay is
+    // closed so the next one starts with them.
+    history: VecDeque<String>,
+    // the history entry being shown in the prompt, if up has been pressed
+    history_pos: Option<usize>,
+    // what was typed before going back through the history
+    draft: String,
+}
+
+impl ShellOverlayState {
+    pub fn new(serial: Option<String>, history: VecDeque<String>) -> Self {
+        Self {
+            serial,
+            stage: Stage::Input(String::new()),
+            history,
+            history_pos: None,
+            draft: String::new(),
+        }
+    }
+
+    /// The commands run so far, to pass to the next overlay
+    pub fn into_history(self) -> VecDeque<String> {
+        self.history
+    }
+
+    /// Runs the most recent command again, returning `false` if nothing has been run yet
+    pub fn rerun_last(&mut self) -> bool {
+        match self.history.back().cloned() {
+            Some(command) => {
+                self.run(command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn run(&mut self, command: String) {
+        if self.history.back() != Some(&command) {
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(command.clone());
+        }
+        self.history_pos = None;
+
+        let output = adb::shell(adb::transport(), self.serial.as_deref(), &command);
+        self.stage = Stage::Running {
+            command,
+            output: Box::pin(output),
+            lines: Vec::new(),
+            done: false,
+            scroll: 0,
+        };
+    }
+
+    /// Handles a key press, returning `false` once the overlay has been dismissed
+    pub fn key(&mut self, key: KeyEvent) -> bool {
+        match &mut self.stage {
+            Stage::Input(input) => match key.code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Up if !self.history.is_empty() => {
+                    let pos = match self.history_pos {
+                        Some(pos) => pos.saturating_sub(1),
+                        None => {
+                            self.draft = std::mem::take(input);
+                            self.history.len() - 1
+                        }
+                    };
+                    self.history_pos = Some(pos);
+                    *input = self.history[pos].clone();
+                }
+                KeyCode::Down => {
+                    if let Some(pos) = self.history_pos {
+                        if pos + 1 < self.history.len() {
+                            self.history_pos = Some(pos + 1);
+                            *input = self.history[pos + 1].clone();
+                        } else {
+                            self.history_pos = None;
+                            *input = std::mem::take(&mut self.draft);
+                        }
+                    }
+                }
+                KeyCode::Enter if !input.trim().is_empty() => {
+                    let command = std::mem::take(input);
+                    self.run(command);
+                }
+                KeyCode::Esc => return false,
+                _ => {}
+            },
+            Stage::Running { lines, scroll, .. } => match key.code {
+                KeyCode::Up | KeyCode::Char('k') => {
+                    *scroll = (*scroll + 1).min(lines.len().saturating_sub(1))
+                }
+                KeyCode::Down | KeyCode::Char('j') => *scroll = scroll.saturating_sub(1),
+                KeyCode::End => *scroll = 0,
+                // dropping the output kills the command if it's still running
+                KeyCode::Esc => return false,
+                _ => {}
+            },
+        }
+
+        true
+    }
+
+    /// Waits for the next line of output. Never completes once the command has exited.
+    pub async fn poll(&mut self) {
+        if let Stage::Running {
+            output,
+            lines,
+            done: done @ false,
+            scroll,
+            ..
+        } = &mut self.stage
+        {
+            match output.next().await {
+                Some(Ok(line)) => {
+                    lines.push(line);
+                    // keep the same lines in view while scrolled up
+                    if *scroll > 0 {
+                        *scroll += 1;
+                    }
+                    return;
+                }
+                Some(Err(err)) => lines.push(format!("error: {err}")),
+                None => {}
+            }
+            *done = true;
+            return;
+        }
+
+        futures::future::pending().await
+    }
+}
+
+/// Polls the overlay if one is open, so it can sit in a `select!` alongside other widgets
+pub async fn poll_overlay(overlay: &mut Option<ShellOverlayState>) {
+    match overlay {
+        Some(overlay) => overlay.poll().await,
+        None => futures::future::pending().await,
+    }
+}
+
+impl StatefulWidget for ShellOverlay {
+    type State = ShellOverlayState;
+
+    fn render(self, area: Rect, buf: &mut tui::buffer::Buffer, state: &mut Self::State) {
+        // center the popup, leaving a margin of the covered UI visible
+        let width = area
+            .width
+            .saturating_sub(area.width / 5)
+            .max(area.width.min(20));
+        let height = match state.stage {
+            Stage::Input(_) => area.height.min(3),
+            Stage::Running { .. } => area.height.saturating_sub(area.height / 5),
+        };
+        let area = Rect::new(
+            area.x + (area.width - width) / 2,
+            area.y + (area.height - height) / 2,
+            width,
+            height,
+        );
+
+        let (title, text) = match &state.stage {
+            Stage::Input(input) => ("adb shell".to_string(), format!(":{input}")),
+            Stage::Running {
+                command,
+                lines,
+                done,
+                scroll,
+                ..
+            } => {
+                let visible = height.saturating_sub(2) as usize;
+                let end = lines.len().saturating_sub(*scroll);
+                let start = end.saturating_sub(visible);
+
+                let status = if *done { "exited" } else { "running" };
+                (
+                    format!("adb shell {command} [{status}]"),
+                    lines[start..end].join("\n"),
+                )
+            }
+        };
+
+        let popup = Paragraph::new(text).block(
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White)),
+        );
+
+        Clear.render(area, buf);
+        popup.render(area, buf);
+    }
+}
@@ -5,44 +5,91 @@ use futures::{Stream, StreamExt};
 use tui::{
     layout::Alignment,
     style::{Color, Style},
+    text::{Span, Spans},
     widgets::{Paragraph, StatefulWidget, Widget, Wrap},
 };
 
-use crate::battery::battery;
+use crate::{
+    battery::{battery_info, battery_ticks, format_battery, BatteryInfo},
+    commands::adb,
+};
 
 type BatteryError = crate::battery::Error;
 
 enum StatusEvent {
-    Battery(Result<i32, BatteryError>),
+    Battery(Result<BatteryInfo, BatteryError>),
+    Uptime(std::io::Result<Duration>),
 }
 
-pub struct StatusBar {}
+/// A rough duration like `3h 12m`, precise enough to tell whether a device rebooted
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m")
+    }
+}
+
+pub struct StatusBar {
+    dropped: usize,
+    decode_errors: usize,
+    rate: Option<f32>,
+}
 
 impl StatusBar {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            dropped: 0,
+            decode_errors: 0,
+            rate: None,
+        }
+    }
+
+    /// Messages received per second
+    pub fn rate(mut self, rate: f32) -> Self {
+        self.rate = Some(rate);
+        self
+    }
+
+    /// Number of log entries that failed to decode, shown when non-zero
+    pub fn decode_errors(mut self, decode_errors: usize) -> Self {
+        self.decode_errors = decode_errors;
+        self
+    }
+
+    /// Number of log messages known to be missing, shown when non-zero
+    pub fn dropped(mut self, dropped: usize) -> Self {
+        self.dropped = dropped;
+        self
     }
 }
 
 pub struct StatusBarState {
     event_stream: Pin<Box<dyn Stream<Item = StatusEvent>>>,
-    battery: Option<Result<i32, BatteryError>>,
+    battery: Option<Result<BatteryInfo, BatteryError>>,
+    uptime: Option<std::io::Result<Duration>>,
 }
 
 impl StatusBarState {
-    pub fn new() -> Self {
+    pub fn new(serial: Option<String>) -> Self {
         let event_stream: Pin<Box<dyn Stream<Item = StatusEvent>>> = Box::pin(stream! {
-            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            let mut ticks = battery_ticks();
 
             loop {
-                interval.tick().await;
-                yield StatusEvent::Battery(battery().await);
+                ticks.tick().await;
+                yield StatusEvent::Battery(battery_info(adb::transport(), serial.as_deref()).await);
+                yield StatusEvent::Uptime(adb::uptime(adb::transport(), serial.as_deref()).await);
             }
         });
 
         Self {
             event_stream,
             battery: None,
+            uptime: None,
         }
     }
 
@@ -51,7 +98,9 @@ impl StatusBarState {
             match event {
                 StatusEvent::Battery(battery) => {
                     self.battery = Some(battery);
-                    return;
+                }
+                StatusEvent::Uptime(uptime) => {
+                    self.uptime = Some(uptime);
                 }
             }
         }
@@ -67,16 +116,37 @@ impl StatefulWidget for StatusBar {
         buf: &mut tui::buffer::Buffer,
         state: &mut Self::State,
     ) {
-        let battery = match state.battery {
-            Some(Ok(battery)) => battery.to_string(),
-            Some(Err(_)) => "err".to_string(),
-            None => "-".to_string(),
+        let (battery, battery_style) = match &state.battery {
+            Some(Ok(battery)) => format_battery(battery),
+            Some(Err(BatteryError::DeviceOffline)) => ("offline".to_string(), Style::default()),
+            Some(Err(_)) => ("err".to_string(), Style::default()),
+            None => ("-".to_string(), Style::default()),
         };
 
-        let status = Paragraph::new(format!("battery: {battery}"))
-            .style(Style::default().bg(Color::Magenta).fg(Color::White))
-            .alignment(Alignment::Right)
-            .wrap(Wrap { trim: false });
+        let mut status = Vec::new();
+        if self.dropped > 0 {
+            status.push(format!("dropped: {}", self.dropped));
+        }
+        if self.decode_errors > 0 {
+            status.push(format!("decode errors: {}", self.decode_errors));
+        }
+        if let Some(rate) = self.rate {
+            status.push(format!("logs/s: {rate:.0}"));
+        }
+        match &state.uptime {
+            Some(Ok(uptime)) => status.push(format!("up {}", format_uptime(*uptime))),
+            Some(Err(_)) => status.push("up: err".to_string()),
+            None => {}
+        }
+        status.push("battery: ".to_string());
+
+        let status = Paragraph::new(Spans::from(vec![
+            Span::raw(status.join(" | ")),
+            Span::styled(battery, battery_style),
+        ]))
+        .style(Style::default().bg(Color::Magenta).fg(Color::White))
+        .alignment(Alignment::Right)
+        .wrap(Wrap { trim: false });
 
         status.render(area, buf)
     }